@@ -1,14 +1,16 @@
 /*
-    This script takes in as input a file that contains a HTML string, renders it in a browser instance, and takes two screenshots in different sizes.
-    During initial setup, run bun run puppeteer browsers install chrome, and copy the executable path to line 8
+    This script takes in as input a file that contains a HTML string, renders it in a browser instance, and takes a screenshot for every viewport.
+    The screenshots are given as a JSON array of { path, width, height, scale, fullPage, type, quality }, where type is png, jpeg or webp.
+    An entry of { path, pdf: { page_size, margin } } prints the page to a pdf instead.
+    Chrome is taken from CHROME_PATH when it's set, otherwise puppeteer uses the one installed by bun run puppeteer browsers install chrome
 */
 const puppeteer = require('puppeteer');
-const fs = require('fs');
 
 (async () => {
+    let browser;
     try {
-        const browser = await puppeteer.launch({
-            executablePath: '/home/vishalds/.cache/puppeteer/chrome/linux-126.0.6478.182/chrome-linux64/chrome',
+        browser = await puppeteer.launch({
+            executablePath: process.env.CHROME_PATH || undefined,
             args: ['--no-sandbox', '--disable-setuid-sandbox']
         });
 
@@ -30,16 +32,41 @@ const fs = require('fs');
             timeout: 60000
         });
 
-        await page.setViewport({ width: 412, height: 915 });
-        await page.screenshot({ path: process.argv[3] });
-
-        await page.setViewport({ width: 1280, height: 800 });
-        await page.screenshot({ path: process.argv[4] });
-
-        await browser.close();
+        const screenshots = JSON.parse(process.argv[3]);
+        for (const screenshot of screenshots) {
+            if (screenshot.pdf) {
+                const margin = screenshot.pdf.margin;
+                await page.pdf({
+                    path: screenshot.path,
+                    format: screenshot.pdf.page_size,
+                    margin: { top: margin, right: margin, bottom: margin, left: margin },
+                    printBackground: true
+                });
+                continue;
+            }
 
+            // a scale below 1 downscales the screenshot, for thumbnails
+            await page.setViewport({
+                width: screenshot.width,
+                height: screenshot.height,
+                deviceScaleFactor: screenshot.scale || 1
+            });
+            await page.screenshot({
+                path: screenshot.path,
+                type: screenshot.type || 'png',
+                fullPage: !!screenshot.fullPage,
+                // png is lossless & doesn't take a quality
+                ...(screenshot.type && screenshot.type !== 'png' ? { quality: screenshot.quality } : {})
+            });
+        }
     } catch (error) {
-        fs.writeFileSync('screenshot_error.log', error.toString());
-        process.exit(1);
+        // stderr ends up in the server's logs
+        console.error(error);
+        process.exitCode = 1;
+    } finally {
+        // a failed render shouldn't leave chrome running
+        if (browser) {
+            await browser.close();
+        }
     }
-})();
\ No newline at end of file
+})();
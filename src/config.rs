@@ -1,10 +1,15 @@
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
 use std::env;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
-use tokio::fs;
+use tokio::sync::mpsc;
 use tokio::time::{self, Duration};
 
 use crate::AppState;
-use crate::models::template::update_template_in_db;
+use crate::services::preview::{ImageFormat, RendererKind, Viewport};
+use crate::models::template::{delete_template_from_db, sync_templates, templates_dir, update_template_in_db};
 
 pub struct Config {
     pub mongodb_uri: String,
@@ -12,44 +17,194 @@ pub struct Config {
     pub anthropic_token: String,
     pub api_addr: String,
     pub server_addr: String,
+    pub revision_retention: usize,
+    pub republish_delay: u64,
+    pub access_token_ttl: i64,
+    pub refresh_token_ttl: i64,
+    pub reset_token_ttl: i64,
+    pub verify_token_ttl: i64,
+    pub verify_resend_interval: i64,
+    pub reset_request_interval: i64,
+    pub require_verified_email: bool,
+    pub portal_url: String,
+    pub api_url: String,
+    pub site_quota: u64,
+    pub user_quota: u64,
+    pub asset_limit: usize,
+    pub preview_workers: usize,
+    pub preview_queue: usize,
+    pub preview_timeout: u64,
+    pub preview_cache_size: u64,
+    pub preview_viewports: Vec<Viewport>,
+    pub preview_format: ImageFormat,
+    pub preview_quality: u8,
+    pub preview_renderer: RendererKind,
+    pub chrome_path: Option<PathBuf>,
+    pub mail_transport: String,
+    pub mail_from: String,
+    pub mail_log: Option<PathBuf>,
+    pub smtp_host: String,
+    pub smtp_username: String,
+    pub smtp_password: String,
 }
 
 impl Config {
     pub fn from_env() -> Self {
         dotenv::dotenv().ok();
+        let chrome_path = env::var("CHROME_PATH").ok().map(PathBuf::from);
         Config {
             mongodb_uri: env::var("MONGODB_URI").expect("MONGODB_URI must be set"),
             secret_key: env::var("SECRET_KEY").expect("SECRET_KEY must be set").as_bytes().to_vec(),
             anthropic_token: env::var("ANTHROPIC_KEY").expect("Anthropic account credentials must be set"),
             api_addr: env::var("API_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string()),
             server_addr: env::var("SERVER_ADDR").unwrap_or_else(|_| "127.0.0.1:5000".to_string()),
+            revision_retention: env::var("REVISION_RETENTION").ok().and_then(|n| n.parse().ok()).unwrap_or(20),
+            republish_delay: env::var("REPUBLISH_DELAY").ok().and_then(|n| n.parse().ok()).unwrap_or(5),
+            access_token_ttl: env::var("ACCESS_TOKEN_TTL").ok().and_then(|n| n.parse().ok()).unwrap_or(15 * 60),
+            refresh_token_ttl: env::var("REFRESH_TOKEN_TTL").ok().and_then(|n| n.parse().ok()).unwrap_or(30 * 24 * 3600),
+            reset_token_ttl: env::var("RESET_TOKEN_TTL").ok().and_then(|n| n.parse().ok()).unwrap_or(3600),
+            verify_token_ttl: env::var("VERIFY_TOKEN_TTL").ok().and_then(|n| n.parse().ok()).unwrap_or(24 * 3600),
+            verify_resend_interval: env::var("VERIFY_RESEND_INTERVAL").ok().and_then(|n| n.parse().ok()).unwrap_or(60),
+            reset_request_interval: env::var("RESET_REQUEST_INTERVAL").ok().and_then(|n| n.parse().ok()).unwrap_or(60),
+            require_verified_email: env::var("REQUIRE_VERIFIED_EMAIL").map(|v| v == "true").unwrap_or(false),
+            portal_url: env::var("PORTAL_URL").unwrap_or_else(|_| "https://zitefy.com".to_string()),
+            api_url: env::var("API_URL").unwrap_or_else(|_| "https://api.zitefy.com".to_string()),
+            // quotas are given in MB
+            site_quota: env::var("SITE_QUOTA").ok().and_then(|n| n.parse::<u64>().ok()).unwrap_or(50) * 1024 * 1024,
+            user_quota: env::var("USER_QUOTA").ok().and_then(|n| n.parse::<u64>().ok()).unwrap_or(200) * 1024 * 1024,
+            asset_limit: env::var("ASSET_LIMIT").ok().and_then(|n| n.parse().ok()).unwrap_or(100),
+            preview_workers: env::var("PREVIEW_WORKERS").ok().and_then(|n| n.parse().ok()).unwrap_or(2),
+            preview_queue: env::var("PREVIEW_QUEUE").ok().and_then(|n| n.parse().ok()).unwrap_or(32),
+            preview_timeout: env::var("PREVIEW_TIMEOUT").ok().and_then(|n| n.parse().ok()).unwrap_or(30),
+            preview_cache_size: env::var("PREVIEW_CACHE_SIZE").ok().and_then(|n| n.parse::<u64>().ok()).unwrap_or(500) * 1024 * 1024,
+            preview_viewports: Viewport::parse_all(&env::var("PREVIEW_VIEWPORTS").unwrap_or_else(|_| "mobile:412x915,desktop:1280x800".to_string()))
+                .unwrap_or_else(|e| panic!("Invalid PREVIEW_VIEWPORTS: {}", e)),
+            preview_format: env::var("PREVIEW_FORMAT").ok().and_then(|format| ImageFormat::parse(&format)).unwrap_or_default(),
+            preview_quality: env::var("PREVIEW_QUALITY").ok().and_then(|n| n.parse().ok()).unwrap_or(80).clamp(1, 100),
+            preview_renderer: RendererKind::parse(&env::var("PREVIEW_RENDERER").unwrap_or_else(|_| "puppeteer".to_string()), chrome_path.as_deref())
+                .unwrap_or_else(|e| panic!("Invalid PREVIEW_RENDERER: {}", e)),
+            chrome_path,
+            mail_transport: env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "log".to_string()),
+            mail_from: env::var("MAIL_FROM").unwrap_or_else(|_| "zitefy <noreply@zitefy.com>".to_string()),
+            mail_log: env::var("MAIL_LOG").ok().map(PathBuf::from),
+            smtp_host: env::var("SMTP_HOST").unwrap_or_default(),
+            smtp_username: env::var("SMTP_USERNAME").unwrap_or_default(),
+            smtp_password: env::var("SMTP_PASSWORD").unwrap_or_default(),
         }
     }
 }
 
-// Right now, this seems like the best way to manage the upload of templates from github
-// This function is very expensive, so a better method would be to write a dedicated endpoint for uploading templates from the site,
-// and adding it to the database along with it. But that's for another day.
+// templates from github land in the templates directory through CI. everything in it is synced once on startup,
+// to catch up on whatever changed while the server was down, & then only the template directories that change.
 pub async fn monitor_templates_directory(app_state: Arc<AppState>) {
-    let templates_dir = format!("{}/.zitefy/templates", env::var("HOME").unwrap());
+    let dir = match templates_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to find the templates directory: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("Failed to create the templates directory: {}", e);
+    }
+
+    sync_all(&app_state).await;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(&dir, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+
+    // the watcher has to be kept around for as long as the events are needed
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Failed to watch the templates directory, falling back to an hourly scan: {}", e);
+            poll_templates_directory(app_state).await;
+            return;
+        }
+    };
+
+    while let Some(event) = rx.recv().await {
+        let mut changed = HashSet::new();
+        changed_templates(event, &dir, &mut changed);
+
+        // CI copies a template over file by file, so the changes are let to settle before syncing
+        while let Ok(Some(event)) = time::timeout(TEMPLATE_SETTLE_TIME, rx.recv()).await {
+            changed_templates(event, &dir, &mut changed);
+        }
+
+        for path in changed {
+            let result = if path.is_dir() {
+                update_template_in_db(&path, &app_state).await
+            } else {
+                delete_template_from_db(&path, &app_state).await
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to sync template {}: {}", path.display(), e);
+            }
+        }
+    }
+}
 
+const TEMPLATE_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+// adds the directories of the templates an event touched to the set
+fn changed_templates(event: notify::Result<Event>, dir: &Path, changed: &mut HashSet<PathBuf>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            eprintln!("Error while watching the templates directory: {}", e);
+            return;
+        }
+    };
+
+    // reading files is an event too, only finished writes matter
+    if let EventKind::Access(kind) = event.kind {
+        if kind != AccessKind::Close(AccessMode::Write) {
+            return;
+        }
+    }
+
+    for path in event.paths {
+        let mut components = match path.strip_prefix(dir) {
+            Ok(relative) => relative.components(),
+            Err(_) => continue,
+        };
+        let template = match components.next() {
+            Some(Component::Normal(name)) if !name.to_string_lossy().starts_with('.') => dir.join(name),
+            _ => continue,
+        };
+        // rendering a preview writes to the template's directory, which shouldn't trigger another sync
+        if components.next().is_some_and(|c| c.as_os_str() == "previews") {
+            continue;
+        }
+        changed.insert(template);
+    }
+}
+
+// only used if the directory can't be watched
+async fn poll_templates_directory(app_state: Arc<AppState>) {
     let mut interval = time::interval(Duration::from_secs(60*60));
+    interval.tick().await;
 
     loop {
         interval.tick().await;
+        sync_all(&app_state).await;
+    }
+}
 
-        match fs::read_dir(&templates_dir).await {
-            Ok(mut entries) => {
-                while let Some(entry) = entries.next_entry().await.unwrap() {
-                    let path = entry.path();
-                    if path.is_dir() {
-                        if let Err(e) = update_template_in_db(&path, &app_state).await {
-                            eprintln!("Failed to update template in database: {}", e);
-                        }
-                    }
-                }
-            },
-            Err(e) => eprintln!("Failed to read templates directory: {}", e),
-        }
+async fn sync_all(app_state: &Arc<AppState>) {
+    match sync_templates(app_state).await {
+        Ok((_, failed)) => {
+            for (_, e) in failed {
+                eprintln!("Failed to update template in database: {}", e);
+            }
+        },
+        Err(e) => eprintln!("Failed to read templates directory: {}", e),
     }
 }
@@ -0,0 +1,158 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+
+// the one error type used across handlers, models & services.
+// every error reaches the client as { "error": { "code": "...", "message": "..." } },
+// invalid fields come with { "fields": [{ "selector": "...", "message": "..." }] } in the error as well
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Validation(String),
+    // data that doesn't fit a template, with what's wrong with each field
+    InvalidFields(Vec<FieldError>),
+    Conflict(String),
+    RateLimited(String),
+    Db(mongodb::error::Error),
+    Io(std::io::Error),
+    // anything else that goes wrong on our end, like hashing a password or serializing a response
+    Internal(String),
+    Render(String),
+    Upstream(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub selector: String,
+    pub message: String,
+}
+
+impl AppError {
+    // machine readable, so that the frontend doesn't have to match on messages
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::Validation(_) => "validation",
+            AppError::InvalidFields(_) => "invalid_fields",
+            AppError::Conflict(_) => "conflict",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::Db(_) => "database",
+            AppError::Io(_) => "io",
+            AppError::Internal(_) => "internal",
+            AppError::Render(_) => "render",
+            AppError::Upstream(_) => "upstream",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::Validation(message)
+            | AppError::Conflict(message)
+            | AppError::RateLimited(message)
+            | AppError::Render(message)
+            | AppError::Upstream(message) => write!(f, "{}", message),
+            AppError::InvalidFields(errors) => write!(f, "{} of the fields are invalid", errors.len()),
+            AppError::Db(e) => write!(f, "database error: {}", e),
+            AppError::Io(e) => write!(f, "io error: {}", e),
+            AppError::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Validation(_) | AppError::InvalidFields(_) => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Db(_) | AppError::Io(_) | AppError::Internal(_) | AppError::Render(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        // the details of internal errors only go to the logs
+        let message = match self {
+            AppError::Db(_) | AppError::Io(_) | AppError::Internal(_) => {
+                log::error!("{}", self);
+                String::from("Internal error, contact admin.")
+            },
+            _ => self.to_string(),
+        };
+
+        let mut error = json!({ "code": self.code(), "message": message });
+        if let AppError::InvalidFields(errors) = self {
+            error["fields"] = json!(errors);
+        }
+
+        HttpResponse::build(self.status_code()).json(json!({ "error": error }))
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(e: mongodb::error::Error) -> Self {
+        AppError::Db(e)
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for AppError {
+    fn from(e: mongodb::bson::ser::Error) -> Self {
+        AppError::Db(e.into())
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Io(e)
+    }
+}
+
+// json that comes from a client is checked where it's parsed, so whatever gets here is our own
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+impl From<std::env::VarError> for AppError {
+    fn from(e: std::env::VarError) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for AppError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for AppError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => AppError::Io(e),
+            e => AppError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<bcrypt::BcryptError> for AppError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
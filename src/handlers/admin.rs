@@ -0,0 +1,367 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+use crate::handlers::auth::AdminUser;
+use crate::models::site::Site;
+use crate::models::template::{sync_templates, update_template_in_db, Template};
+use crate::models::user::{User, UserSummary};
+use crate::AppState;
+
+const USERS_PER_PAGE: i64 = 50;
+
+#[derive(Deserialize, ToSchema)]
+struct UserSearchQuery {
+    query: Option<String>,
+    page: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ModerationRequest {
+    reason: String,
+}
+
+fn parse_id(id: &str, what: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(id).map_err(|_| AppError::Validation(format!("Invalid {} ID", what)))
+}
+
+// the search is a plain substring match, so regex characters in it are escaped
+fn escape_regex(query: &str) -> String {
+    query.chars().fold(String::new(), |mut escaped, c| {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+async fn get_user(id: ObjectId, app_state: &Arc<AppState>) -> Result<User, AppError> {
+    let users: Collection<User> = app_state.db.collection("users");
+    users
+        .find_one(doc! { "_id": id }, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    params(
+        ("query" = Option<String>, Query, description = "part of the username, email or name to look for"),
+        ("page" = Option<u64>, Query, description = "page of the results, starting at 0. 50 users per page")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The matching users, oldest first", body = Vec<UserSummary>),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn list_users(
+    _: AdminUser,
+    req: HttpRequest,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<UserSearchQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Invalid query".into()))?;
+
+    let filter = match query.query.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        Some(q) => {
+            let pattern = escape_regex(q);
+            doc! {
+                "$or": [
+                    { "username": { "$regex": &pattern, "$options": "i" } },
+                    { "email": { "$regex": &pattern, "$options": "i" } },
+                    { "name": { "$regex": &pattern, "$options": "i" } }
+                ]
+            }
+        },
+        None => Document::new(),
+    };
+
+    let options = FindOptions::builder()
+        .sort(doc! { "_id": 1 })
+        .skip(query.page.unwrap_or(0) * USERS_PER_PAGE as u64)
+        .limit(USERS_PER_PAGE)
+        .build();
+
+    let users: Collection<User> = app_state.db.collection("users");
+    let users: Vec<User> = users.find(filter, options).await?.try_collect().await?;
+    Ok(HttpResponse::Ok().json(users.into_iter().map(UserSummary::from).collect::<Vec<_>>()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/suspend",
+    request_body = ModerationRequest,
+    params(
+        ("id" = String, Path, description = "ID of the user")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The user has been logged out everywhere & their page is no longer served", body = String),
+        (status = 400, description = "Invalid user ID, or an admin trying to suspend themselves"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn suspend_user(
+    admin: AdminUser,
+    id: web::Path<String>,
+    payload: web::Json<ModerationRequest>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = parse_id(&id, "user")?;
+    if user_id == admin.0.id() {
+        return Err(AppError::Validation("Admins can't suspend themselves".into()));
+    }
+
+    let user = get_user(user_id, &app_state).await?;
+    user.suspend(payload.reason.clone(), admin.0.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().body("User suspended"))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{id}/suspend",
+    params(
+        ("id" = String, Path, description = "ID of the user")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The user can login again & their active site is back up", body = String),
+        (status = 400, description = "Invalid user ID"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn unsuspend_user(
+    _: AdminUser,
+    id: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let user = get_user(parse_id(&id, "user")?, &app_state).await?;
+    user.unsuspend(&app_state).await?;
+    Ok(HttpResponse::Ok().body("User unsuspended"))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{id}",
+    params(
+        ("id" = String, Path, description = "ID of the user")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The account & everything in it has been deleted", body = String),
+        (status = 400, description = "Invalid user ID, or an admin trying to delete themselves"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn delete_user(
+    admin: AdminUser,
+    id: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = parse_id(&id, "user")?;
+    if user_id == admin.0.id() {
+        return Err(AppError::Validation("Admins can delete their own account from the profile".into()));
+    }
+
+    let user = get_user(user_id, &app_state).await?;
+    user.delete(&app_state).await?;
+    Ok(HttpResponse::Ok().body("User deleted"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/sites/{id}/takedown",
+    request_body = ModerationRequest,
+    params(
+        ("id" = String, Path, description = "ID of the site")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The site is off zitefy.com & can't be published until it's restored", body = String),
+        (status = 400, description = "Invalid site ID"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn take_down_site(
+    admin: AdminUser,
+    id: web::Path<String>,
+    payload: web::Json<ModerationRequest>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let mut site = Site::from(parse_id(&id, "site")?, &app_state).await?;
+    site.take_down(payload.reason.clone(), admin.0.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().body("Site taken down"))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/sites/{id}/takedown",
+    params(
+        ("id" = String, Path, description = "ID of the site")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The site is back up as it was last published", body = String),
+        (status = 400, description = "Invalid site ID"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn restore_site(
+    _: AdminUser,
+    id: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let mut site = Site::from(parse_id(&id, "site")?, &app_state).await?;
+    site.lift_take_down(&app_state).await?;
+    Ok(HttpResponse::Ok().body("Site restored"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/sites/{id}/preview",
+    params(
+        ("id" = String, Path, description = "ID of the site")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The previews of the site have been rendered again", body = String),
+        (status = 400, description = "Invalid site ID"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn regenerate_preview(
+    _: AdminUser,
+    id: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let site = Site::from(parse_id(&id, "site")?, &app_state).await?;
+    site.regenerate_preview(&app_state).await?;
+    Ok(HttpResponse::Ok().body("Preview regenerated"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/templates/sync",
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "How many templates were added or updated from the templates directory, and the ones that failed"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn sync_all_templates(
+    _: AdminUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let (synced, failed) = sync_templates(&app_state).await?;
+    let failed: Vec<_> = failed
+        .into_iter()
+        .map(|(dir, e)| json!({ "dir": dir, "error": e.to_string() }))
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "synced": synced, "failed": failed })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/templates/{id}/sync",
+    params(
+        ("id" = String, Path, description = "ID of the template")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The template has been read from its directory again. Its previews are rendered again if its files changed", body = String),
+        (status = 400, description = "Invalid template ID"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "Template not found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "admin"
+)]
+async fn sync_template(
+    _: AdminUser,
+    id: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let templates: Collection<Template> = app_state.db.collection("templates");
+    let template = templates
+        .find_one(doc! { "_id": parse_id(&id, "template")? }, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Template not found".into()))?;
+
+    update_template_in_db(Path::new(&template.dir_path), &app_state).await?;
+    Ok(HttpResponse::Ok().body("Template synced"))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .route("/users", web::get().to(list_users))
+            .route("/users/{id}/suspend", web::post().to(suspend_user))
+            .route("/users/{id}/suspend", web::delete().to(unsuspend_user))
+            .route("/users/{id}", web::delete().to(delete_user))
+            .route("/sites/{id}/takedown", web::post().to(take_down_site))
+            .route("/sites/{id}/takedown", web::delete().to(restore_site))
+            .route("/sites/{id}/preview", web::post().to(regenerate_preview))
+            .route("/templates/sync", web::post().to(sync_all_templates))
+            .route("/templates/{id}/sync", web::post().to(sync_template))
+    );
+}
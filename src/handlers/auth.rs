@@ -0,0 +1,196 @@
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use chrono::{Duration, Utc};
+use futures::future::LocalBoxFuture;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::models::session::Session;
+use crate::models::site::Site;
+use crate::models::user::{Role, User};
+use crate::AppState;
+
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    sub: String,
+    sid: String,
+    exp: usize,
+}
+
+// access tokens are short lived, the refresh token of the session is used to get a new one
+pub fn generate_token(user_id: &ObjectId, session_id: &ObjectId, secret: &[u8], ttl: i64) -> String {
+    let expiration = Utc::now()
+        .checked_add_signed(Duration::seconds(ttl))
+        .expect("valid timestamp")
+        .timestamp();
+
+    let claims = Claims {
+        sub: user_id.to_hex(),
+        sid: session_id.to_hex(),
+        exp: expiration as usize,
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret),
+    )
+    .unwrap()
+}
+
+pub fn verify_token(
+    token: &str,
+    secret: &[u8],
+) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret),
+        &Validation::default(),
+    )
+}
+
+// returns the ids of the user & the session the token was issued for
+async fn get_session_from_token(
+    req: &HttpRequest,
+    app_state: &Arc<AppState>,
+) -> Result<(ObjectId, ObjectId), AppError> {
+    let token = match req.headers().get("Authorization") {
+        Some(header_value) => header_value
+            .to_str()
+            .ok()
+            .and_then(|token_str| token_str.strip_prefix("Bearer "))
+            .ok_or_else(|| AppError::Unauthorized("Invalid authorization header format".into()))?,
+        None => return Err(AppError::Unauthorized("No authorization header provided".into())),
+    };
+
+    let token_data = verify_token(token, &app_state.secret_key)
+        .map_err(|_| AppError::Unauthorized("Invalid token. Has likely expired.".into()))?;
+
+    let (user_id, session_id) = match (
+        ObjectId::parse_str(&token_data.claims.sub),
+        ObjectId::parse_str(&token_data.claims.sid),
+    ) {
+        (Ok(user_id), Ok(session_id)) => (user_id, session_id),
+        _ => return Err(AppError::Unauthorized("Invalid token data".into())),
+    };
+
+    if !Session::is_active(session_id, app_state).await? {
+        return Err(AppError::Unauthorized("This session has been logged out.".into()));
+    }
+    Ok((user_id, session_id))
+}
+
+// the logged in user making the request. taking it as an argument is all a handler needs to require a login.
+pub struct AuthUser {
+    pub user: User,
+    pub session: ObjectId,
+}
+
+impl AuthUser {
+    pub fn id(&self) -> ObjectId {
+        self.user._id
+    }
+
+    pub fn require(&self, role: Role) -> Result<(), AppError> {
+        if !self.user.has_role(role) {
+            return Err(AppError::Forbidden(format!("This needs the {} role", role.as_str())));
+        }
+        Ok(())
+    }
+
+    // the site, as long as it belongs to this user. anything that changes, publishes or activates a site goes through here.
+    pub async fn owned_site(&self, site_id: ObjectId, app_state: &Arc<AppState>) -> Result<Site, AppError> {
+        let site = Site::from(site_id, app_state).await?;
+        if site.user != self.user._id {
+            return Err(AppError::Forbidden("This user isn't authorized to use this site".into()));
+        }
+        Ok(site)
+    }
+
+    // like owned_site, but admins can get at any site. only for routes that moderation needs,
+    // like looking through a site or removing it.
+    pub async fn moderated_site(&self, site_id: ObjectId, app_state: &Arc<AppState>) -> Result<Site, AppError> {
+        let site = Site::from(site_id, app_state).await?;
+        if site.user != self.user._id {
+            self.require(Role::Admin)
+                .map_err(|_| AppError::Forbidden("This user isn't authorized to use this site".into()))?;
+        }
+        Ok(site)
+    }
+
+    // publishing can be restricted to verified accounts to keep spam off zitefy.com
+    pub fn can_publish(&self, app_state: &AppState) -> Result<(), AppError> {
+        if app_state.require_verified_email && !self.user.verified {
+            return Err(AppError::Forbidden("Verify your email before publishing a site".into()));
+        }
+        Ok(())
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let app_state = req
+                .app_data::<web::Data<Arc<AppState>>>()
+                .expect("app state is registered on the api server")
+                .get_ref()
+                .clone();
+
+            let (user_id, session) = get_session_from_token(&req, &app_state).await?;
+
+            let users: Collection<User> = app_state.db.collection("users");
+            let user = users
+                .find_one(doc! { "_id": user_id }, None)
+                .await?
+                .ok_or_else(|| AppError::Unauthorized("This account no longer exists".into()))?;
+
+            if user.suspended.is_some() {
+                return Err(AppError::Forbidden("This account has been suspended".into()));
+            }
+
+            Ok(AuthUser { user, session })
+        })
+    }
+}
+
+// for the /admin routes
+pub struct AdminUser(pub AuthUser);
+
+impl FromRequest for AdminUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let auth = AuthUser::from_request(req, payload);
+        Box::pin(async move {
+            let auth = auth.await?;
+            auth.require(Role::Admin)?;
+            Ok(AdminUser(auth))
+        })
+    }
+}
+
+// for routes that work without a login but can do more with one.
+// no authorization header means no user, but a bad token is still an error.
+pub struct MaybeAuthUser(pub Option<AuthUser>);
+
+impl FromRequest for MaybeAuthUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if !req.headers().contains_key("Authorization") {
+            return Box::pin(async { Ok(MaybeAuthUser(None)) });
+        }
+
+        let auth = AuthUser::from_request(req, payload);
+        Box::pin(async move { Ok(MaybeAuthUser(Some(auth.await?))) })
+    }
+}
@@ -1,11 +1,14 @@
 pub mod user;
 pub mod template;
 pub mod site;
+pub mod auth;
+pub mod admin;
 
 use actix_web::{web, HttpResponse, HttpRequest, post};
 use bytes::Bytes;
 use std::sync::Arc;
- use crate::AppState;
+use crate::error::AppError;
+use crate::AppState;
 
 #[utoipa::path(
     post,
@@ -15,7 +18,7 @@ use std::sync::Arc;
         (status = 400, description = "Bad request. Check the API docs."),
         (status = 401, description = "Bad Anthropic token"),
         (status = 404, description = "No such route in the API"),
-        (status = 500, description = "Internal error communicating with the API")
+        (status = 502, description = "Internal error communicating with the API")
     ),
     tag = "proxy"
 )]
@@ -24,7 +27,7 @@ pub async fn proxy_anthropic(
     req: HttpRequest,
     payload: Bytes,
     app_state: web::Data<Arc<AppState>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, AppError> {
     let bearer_token = match req.headers().get("Authorization") {
         Some(token) => token.to_str().unwrap_or("").trim_start_matches("Bearer "),
         None => &app_state.anthropic_token,
@@ -37,35 +40,35 @@ pub async fn proxy_anthropic(
         .header("content-type", "application/json")
         .body(payload);
 
-    match anthropic_req.send().await {
-        Ok(response) => {
-            let status = response.status();
-            let body = response.bytes().await.unwrap_or_default();
-            
-            // Get the Origin header from the incoming request
-            let origin = req.headers().get("Origin").and_then(|h| h.to_str().ok());
-            
-            // Check if the origin is allowed
-            let allowed_origin = match origin {
-                Some("https://zitefy.com") => Some("https://zitefy.com"),
-                Some("https://www.zitefy.com") => Some("https://www.zitefy.com"),
-                Some("http://localhost:3000") => Some("https://localhost:3000"),
-                Some("http://localhost:5000") => Some("https://localhost:5000"),
-                _ => None,
-            };
-            
-            let mut builder = HttpResponse::build(status);
-            
-            // Set the Access-Control-Allow-Origin header if the origin is allowed
-            if let Some(allowed) = allowed_origin {
-                builder.append_header(("Access-Control-Allow-Origin", allowed));
-            }
-            
-            builder
-                .append_header(("Access-Control-Allow-Methods", "POST, OPTIONS"))
-                .append_header(("Access-Control-Allow-Headers", "Content-Type, Authorization"))
-                .body(body)
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    let response = anthropic_req
+        .send()
+        .await
+        .map_err(|e| AppError::Upstream(format!("Error: {}", e)))?;
+
+    let status = response.status();
+    let body = response.bytes().await.unwrap_or_default();
+    
+    // Get the Origin header from the incoming request
+    let origin = req.headers().get("Origin").and_then(|h| h.to_str().ok());
+    
+    // Check if the origin is allowed
+    let allowed_origin = match origin {
+        Some("https://zitefy.com") => Some("https://zitefy.com"),
+        Some("https://www.zitefy.com") => Some("https://www.zitefy.com"),
+        Some("http://localhost:3000") => Some("https://localhost:3000"),
+        Some("http://localhost:5000") => Some("https://localhost:5000"),
+        _ => None,
+    };
+    
+    let mut builder = HttpResponse::build(status);
+    
+    // Set the Access-Control-Allow-Origin header if the origin is allowed
+    if let Some(allowed) = allowed_origin {
+        builder.append_header(("Access-Control-Allow-Origin", allowed));
     }
+    
+    Ok(builder
+        .append_header(("Access-Control-Allow-Methods", "POST, OPTIONS"))
+        .append_header(("Access-Control-Allow-Headers", "Content-Type, Authorization"))
+        .body(body))
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
-use actix_multipart::Multipart;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_files::NamedFile;
+use actix_multipart::{Field, Multipart, MultipartError};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web::web::Json;
 use mongodb::bson::{doc, oid::ObjectId};
 use serde::Deserialize;
@@ -8,8 +9,12 @@ use std::sync::Arc;
 use futures::{TryStreamExt, StreamExt};
 use utoipa::ToSchema;
 
+use crate::error::AppError;
+use crate::models::revision::Revision;
 use crate::models::site::{Site, Data, preview_code};
-use crate::handlers::user::get_user_id_from_token;
+use crate::models::template_version::Upgrade;
+use crate::handlers::auth::AuthUser;
+use crate::services::preview::PdfOptions;
 use crate::AppState;
 
 #[derive(Deserialize, ToSchema)]
@@ -37,7 +42,8 @@ struct ResourceRequest {
 #[derive(Deserialize, ToSchema)]
 struct PreviewRequest {
     id: String,
-    wide: Option<bool>
+    wide: Option<bool>,
+    viewport: Option<String>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -48,6 +54,38 @@ struct CodePreviewRequest {
     data: Vec<Data>
 }
 
+#[derive(Deserialize, ToSchema)]
+struct SiteQuery {
+    id: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PdfQuery {
+    id: String,
+    page_size: Option<String>,
+    margin: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DiffRequest {
+    site: String,
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RestoreRequest {
+    site_id: String,
+    revision_id: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RenameAssetRequest {
+    site_id: String,
+    from: String,
+    to: String,
+}
+
 #[derive(Deserialize, ToSchema)]
 struct RenameRequest {
     site_id: String,
@@ -69,24 +107,15 @@ struct RenameRequest {
     tag = "site"
 )]
 async fn new_site(
-    req: HttpRequest,
+    auth: AuthUser,
     new_site_req: Json<NewSiteRequest>,
     app_state: web::Data<Arc<AppState>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(resp) => return Ok(resp),
-    };
+) -> Result<HttpResponse, AppError> {
+    let template_id = ObjectId::parse_str(&new_site_req.template_id)
+        .map_err(|_| AppError::Validation("Invalid template ID".into()))?;
 
-    let template_id = match ObjectId::parse_str(&new_site_req.template_id) {
-        Ok(id) => id,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid template ID")),
-    };
-
-    match Site::new(template_id, user_id, &app_state).await {
-        Ok(site_id) => Ok(HttpResponse::Ok().json(doc! { "site_id": site_id.to_hex() })),
-        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
-    }
+    let site_id = Site::new(template_id, auth.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().json(doc! { "site_id": site_id.to_hex() }))
 }
 
 #[utoipa::path(
@@ -103,16 +132,12 @@ async fn new_site(
 async fn view_site(
     save_data_req: Json<Request>,
     app_state: web::Data<Arc<AppState>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let site_id = match ObjectId::parse_str(&save_data_req.site_id) {
-        Ok(id) => id,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
-    };
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&save_data_req.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
 
-    match Site::from(site_id, &app_state).await {
-        Ok(site) => Ok(HttpResponse::Ok().json(site.data)),
-        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string()))
-    }
+    let site = Site::from(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(site.data))
 }
 
 #[utoipa::path(
@@ -125,34 +150,24 @@ async fn view_site(
     responses(
         (status = 200, description = "The ID of the new site", body = String),
         (status = 401, description = "Expired/invalid access token"),
-        (status = 400, description = "Invalid site ID"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID, or data that doesn't fit the fields of the template. Those come with an error for each field under `fields`"),
         (status = 500, description = "Internal error, contact admin.")
     ),
     tag = "site"
 )]
 async fn save_site(
-    req: HttpRequest,
+    auth: AuthUser,
     payload: Json<SaveDataRequest>,
     app_state: web::Data<Arc<AppState>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(resp) => return Ok(resp),
-    };
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&payload.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
 
-    let site_id = match ObjectId::parse_str(&payload.site_id) {
-        Ok(id) => id,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
-    };
+    auth.owned_site(site_id, &app_state).await?;
 
-    if !Site::is_owner(site_id, user_id, &app_state).await? {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
-    }
-
-    match Site::save(site_id, payload.data.clone(), &app_state).await {
-        Ok(_) => Ok(HttpResponse::Ok().body("Data saved successfully")),
-        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
-    }
+    Site::save(site_id, payload.data.clone(), auth.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().body("Data saved successfully"))
 }
 
 #[utoipa::path(
@@ -164,34 +179,22 @@ async fn save_site(
     responses(
         (status = 200, description = "The current name of this site", body = String),
         (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
         (status = 400, description = "Invalid site ID"),
         (status = 500, description = "Internal error, contact admin.")
     ),
     tag = "site"
 )]
 async fn rename(
-    req: HttpRequest,
+    auth: AuthUser,
     payload: Json<RenameRequest>,
     app_state: web::Data<Arc<AppState>>
-) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(resp) => return Ok(resp),
-    };
-
-    let site_id = match ObjectId::parse_str(&payload.site_id) {
-        Ok(id) => id,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
-    };
-
-    if !Site::is_owner(site_id, user_id, &app_state).await? {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
-    }
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&payload.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
 
-    match Site::from(site_id, &app_state).await {
-        Ok(mut site) => Ok(HttpResponse::Ok().body(site.rename(payload.new_name.clone(), &app_state).await)),
-        Err(_) => Ok(HttpResponse::NotFound().body("site with this id wasn't found"))
-    }
+    let mut site = auth.owned_site(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().body(site.rename(payload.new_name.clone(), &app_state).await))
 }
 
 #[utoipa::path(
@@ -201,72 +204,57 @@ async fn rename(
         ("bearer_auth" = [])
     ),
     responses(
-        (status = 200, description = "The URL's of all assets", body = Vec<String>),
+        (status = 200, description = "Every asset of the site, with the uploaded one", body = Vec<Asset>),
         (status = 401, description = "Expired/invalid access token"),
-        (status = 400, description = "Invalid site ID"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID, a type of file that can't be uploaded, or one that would go over the quota of the site or the account"),
         (status = 500, description = "Internal error, contact admin.")
     ),
     tag = "site"
 )]
 async fn save_resource(
-    req: HttpRequest,
+    auth: AuthUser,
     mut payload: Multipart,
     app_state: web::Data<Arc<AppState>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(resp) => return Ok(resp),
-    };
-
+) -> Result<HttpResponse, AppError> {
     let mut site_id = None;
     let mut file_name = None;
     let mut file_content = Vec::new();
 
     while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_disposition = field.content_disposition().unwrap();
-        let name = content_disposition.get_name().unwrap();
+        let name = field_name(&field)?;
 
         if name == "site_id" {
             while let Some(Ok(chunk)) = field.next().await {
-                site_id = Some(String::from_utf8(chunk.to_vec()).unwrap());
+                site_id = Some(field_string(&chunk)?);
             }
         } else if name == "file_name" {
             while let Some(Ok(chunk)) = field.next().await {
-                file_name = Some(String::from_utf8(chunk.to_vec()).unwrap());
+                file_name = Some(field_string(&chunk)?);
             }
         } else if name == "file" {
             while let Some(Ok(chunk)) = field.next().await {
                 file_content.extend_from_slice(&chunk);
+                // nothing bigger than the quota can make it, so there's no point in reading the rest
+                if file_content.len() as u64 > app_state.site_quota {
+                    return Err(AppError::Validation(format!(
+                        "Assets can't be bigger than the storage quota of a site, {} MB",
+                        app_state.site_quota / (1024 * 1024)
+                    )));
+                }
             }
         }
     }
 
-    let site_id = match site_id {
-        Some(id) => match ObjectId::parse_str(&id) {
-            Ok(oid) => oid,
-            Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
-        },
-        None => return Ok(HttpResponse::BadRequest().body("Site ID not provided")),
-    };
-
-    let file_name = match file_name {
-        Some(name) => name,
-        None => return Ok(HttpResponse::BadRequest().body("File name not provided")),
-    };
+    let site_id = site_id.ok_or_else(|| AppError::Validation("Site ID not provided".into()))?;
+    let site_id = ObjectId::parse_str(&site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
 
-    if !Site::is_owner(site_id, user_id, &app_state).await? {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
-    }
+    let file_name = file_name.ok_or_else(|| AppError::Validation("File name not provided".into()))?;
 
-    match Site::from(site_id, &app_state).await {
-        Ok(site) => {
-            match site.save_resource(&file_name, &file_content).await {
-                Ok(response) => Ok(HttpResponse::Ok().json(response)),
-                Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
-            }
-        },
-        Err(_) => Ok(HttpResponse::NotFound().body("site with this id wasn't found"))
-    }
+    let site = auth.owned_site(site_id, &app_state).await?;
+    let response = site.save_resource(&file_name, &file_content, &app_state).await?;
+    Ok(HttpResponse::Ok().json(response))
 }
 
 #[utoipa::path(
@@ -288,23 +276,118 @@ async fn save_resource(
 async fn retrieve_resource(
     req: HttpRequest,
     app_state: web::Data<Arc<AppState>>
-) -> Result<HttpResponse, actix_web::Error> {
-    let query = web::Query::<ResourceRequest>::from_query(req.query_string()).unwrap();
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<ResourceRequest>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Pass in the site & the name of the resource".into()))?;
+
+    let site_id = ObjectId::parse_str(&query.site)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = Site::from(site_id, &app_state).await?;
+    let file = site
+        .retrieve_resource(query.resource.clone())
+        .await
+        .map_err(|_| AppError::NotFound("Requested resource doesn't exist".into()))?;
+    Ok(file.into_response(&req))
+}
 
-    let site_id = match ObjectId::parse_str(&query.site) {
-        Ok(id) => id,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
-    };
+#[utoipa::path(
+    get,
+    path = "/site/assets",
+    params(
+        ("id" = String, Query, description = "site id")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "Every asset of the site with its size, type & when it was uploaded, oldest first", body = Vec<Asset>),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn list_assets(
+    req: HttpRequest,
+    auth: AuthUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<SiteQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Site ID not provided".into()))?;
 
-    match Site::from(site_id, &app_state).await {
-        Ok(site) => {
-            match site.retrieve_resource(query.resource.clone()).await {
-                Ok(file) => Ok(file.into_response(&req)),
-                Err(_) => Ok(HttpResponse::NotFound().body("Requested resource doesn't exist")),
-            }
-        },
-        Err(_) => Ok(HttpResponse::NotFound().body("site likely doesn't exist"))
-    }
+    let site_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = auth.moderated_site(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(site.assets()?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/site/asset",
+    params(
+        ("site" = String, Query, description = "ID of the site in which resource lives"),
+        ("resource" = String, Query, description = "filename of the resource")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "Every asset of the site that's left", body = Vec<Asset>),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site/asset doesn't exist"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn delete_resource(
+    req: HttpRequest,
+    auth: AuthUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<ResourceRequest>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Pass in the site & the name of the resource".into()))?;
+
+    let site_id = ObjectId::parse_str(&query.site)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = auth.owned_site(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(site.delete_resource(&query.resource)?))
+}
+
+#[utoipa::path(
+    put,
+    path = "/site/asset/rename",
+    request_body = RenameAssetRequest,
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "Every asset of the site, with the new name. The html isn't updated, references to the old name have to be changed", body = Vec<Asset>),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID, or a new name that isn't a valid asset name"),
+        (status = 404, description = "site/asset doesn't exist"),
+        (status = 409, description = "There's already an asset with the new name"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn rename_resource(
+    auth: AuthUser,
+    payload: Json<RenameAssetRequest>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&payload.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = auth.owned_site(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(site.rename_resource(&payload.from, &payload.to)?))
 }
 
 #[utoipa::path(
@@ -317,39 +400,22 @@ async fn retrieve_resource(
     responses(
         (status = 200, description = "The source code of the site", body = Code),
         (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Not this user's site"),
         (status = 400, description = "Invalid site ID"),
         (status = 500, description = "Internal error, contact admin.")
     ),
     tag = "site"
 )]
 async fn get_source(
-    req: HttpRequest,
+    auth: AuthUser,
     edit_req: Json<Request>,
     app_state: web::Data<Arc<AppState>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(resp) => return Ok(resp),
-    };
-
-    let site_id = match ObjectId::parse_str(&edit_req.site_id) {
-        Ok(id) => id,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
-    };
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&edit_req.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
 
-    if !Site::is_owner(site_id, user_id, &app_state).await? {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
-    }
-
-    match Site::from(site_id, &app_state).await {
-        Ok(site) => {
-            match site.get_source() {
-                Ok(source) => Ok(HttpResponse::Ok().json(source)),
-                Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string()))
-            }
-        },
-        Err(_) => Ok(HttpResponse::NotFound().body("a site with this id wasn't found")),
-    }
+    let site = auth.moderated_site(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(site.get_source()?))
 }
 
 #[utoipa::path(
@@ -361,68 +427,51 @@ async fn get_source(
     responses(
         (status = 200, description = "The source code of the site", body = String),
         (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Not this user's site"),
         (status = 400, description = "Invalid site ID"),
         (status = 500, description = "Internal error, contact admin.")
     ),
     tag = "site"
 )]
 async fn change_source(
-    req: HttpRequest,
+    auth: AuthUser,
     mut payload: Multipart,
     app_state: web::Data<Arc<AppState>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(resp) => return Ok(resp),
-    };
-
+) -> Result<HttpResponse, AppError> {
     let mut site_id = None;
     let mut html_content = Vec::new();
     let mut css_content = Vec::new();
     let mut js_content = Vec::new();
 
     while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_disposition = field.content_disposition().unwrap();
-        let name = content_disposition.get_name().unwrap();
+        let name = field_name(&field)?;
 
         if name == "site_id" {
-            while let Some(chunk) = field.try_next().await? {
-                site_id = Some(String::from_utf8(chunk.to_vec()).unwrap());
+            while let Some(chunk) = field.try_next().await.map_err(bad_field)? {
+                site_id = Some(field_string(&chunk)?);
             }
         } else if name == "html" {
-            while let Some(chunk) = field.try_next().await? {
+            while let Some(chunk) = field.try_next().await.map_err(bad_field)? {
                 html_content.extend_from_slice(&chunk);
             }
         } else if name == "css" {
-            while let Some(chunk) = field.try_next().await? {
+            while let Some(chunk) = field.try_next().await.map_err(bad_field)? {
                 css_content.extend_from_slice(&chunk);
             }
         } else if name == "js" {
-            while let Some(chunk) = field.try_next().await? {
+            while let Some(chunk) = field.try_next().await.map_err(bad_field)? {
                 js_content.extend_from_slice(&chunk);
             }
         }
     }
 
-    let site_id = match site_id {
-        Some(id) => match ObjectId::parse_str(&id) {
-            Ok(oid) => oid,
-            Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
-        },
-        None => return Ok(HttpResponse::BadRequest().body("Site ID not provided")),
-    };
-
-    if !Site::is_owner(site_id, user_id, &app_state).await? {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
-    }
+    let site_id = site_id.ok_or_else(|| AppError::Validation("Site ID not provided".into()))?;
+    let site_id = ObjectId::parse_str(&site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
 
-    match Site::from(site_id, &app_state).await {
-        Ok(site) => match site.save_source(&html_content, &css_content, &js_content).await {
-            Ok(_) => Ok(HttpResponse::Ok().body("Source code updated")),
-            Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
-        },
-        Err(_) => Ok(HttpResponse::NotFound().body("site with this id wasn't found"))
-    }
+    let site = auth.owned_site(site_id, &app_state).await?;
+    site.save_source(&html_content, &css_content, &js_content, auth.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().body("Source code updated"))
 }
 
 #[utoipa::path(
@@ -430,10 +479,12 @@ async fn change_source(
     path = "/site/preview",
     params(
         ("id" = String, Query, description = "site id"),
-        ("wide" = String, Query, description = "whether the preview should be wide (desktop view) or narrow (mobile view)")
+        ("viewport" = Option<String>, Query, description = "name of the viewport, one of those configured with PREVIEW_VIEWPORTS. mobile & desktop by default"),
+        ("wide" = Option<String>, Query, description = "whether the preview should be wide (desktop view) or narrow (mobile view), when no viewport is given")
     ),
     responses(
         (status = 200, description = "image found", content_type = "image/*"),
+        (status = 400, description = "Invalid site ID or viewport"),
         (status = 404, description = "site/preview not found")
     ),
     tag = "site"
@@ -441,45 +492,466 @@ async fn change_source(
 async fn preview(
     req: HttpRequest,
     app_state: web::Data<Arc<AppState>>
-) -> Result<HttpResponse, actix_web::Error> {
-    let query = web::Query::<PreviewRequest>::from_query(req.query_string()).unwrap();
-    let mut is_mobile = true;
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<PreviewRequest>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Site ID not provided".into()))?;
+    let viewport = match (&query.viewport, query.wide) {
+        (Some(viewport), _) => viewport.as_str(),
+        (None, Some(false)) => "mobile",
+        (None, _) => "desktop",
+    };
+
+    let site_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = Site::from(site_id, &app_state).await?;
+    let file = site.get_preview(viewport, &app_state).await?;
+    Ok(file.into_response(&req))
+}
+
+#[utoipa::path(
+    post,
+    path = "/site/preview_code",
+    request_body = CodePreviewRequest,
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 202, description = "The preview has been queued. poll /site/preview_code/{job} for the images", body = String),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 429, description = "Too many previews are queued already"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn editor_preview(
+    auth: AuthUser,
+    payload: Json<CodePreviewRequest>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let job = preview_code(&payload.html, &payload.css, &payload.js, &payload.data, auth.id(), &app_state).await?;
+    Ok(HttpResponse::Accepted().json(json!({ "job": job })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/site/preview_code/{job}",
+    params(
+        ("job" = String, Path, description = "ID of the job returned when the preview was queued")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "Whether the preview is queued, running, done or failed. once it's done, the images are at /preview/{id}", body = PreviewStatus),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "The preview was queued by another user"),
+        (status = 404, description = "No such job, or it finished too long ago")
+    ),
+    tag = "site"
+)]
+async fn preview_status(
+    auth: AuthUser,
+    job: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let status = app_state.previews.status(&job, auth.id()).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
 
-    if let Some(wide) = query.wide {
-        is_mobile = wide;
+#[utoipa::path(
+    delete,
+    path = "/site/preview_code/{job}",
+    params(
+        ("job" = String, Path, description = "ID of the job returned when the preview was queued")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The preview has been cancelled", body = String),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "The preview was queued by another user"),
+        (status = 404, description = "No such job, or it finished too long ago"),
+        (status = 409, description = "The preview has already finished")
+    ),
+    tag = "site"
+)]
+async fn cancel_preview(
+    auth: AuthUser,
+    job: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    if !app_state.previews.cancel(&job, auth.id()).await? {
+        return Err(AppError::Conflict("This preview has already finished".into()));
     }
+    Ok(HttpResponse::Ok().body("Preview cancelled"))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/site/{id}",
+    params(
+        ("id" = String, Path, description = "ID of the site to delete")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The site has been deleted", body = String),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn delete_site(
+    auth: AuthUser,
+    id: web::Path<String>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(id.as_str())
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
 
-    let site_id = match ObjectId::parse_str(&query.id) {
-        Ok(oid) => oid,
-        Err(_) => return Ok(HttpResponse::BadRequest().body("Invalid site ID")),
+    let site = auth.moderated_site(site_id, &app_state).await?;
+    site.delete(&app_state).await?;
+    Ok(HttpResponse::Ok().body("Site deleted"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/site/publish",
+    request_body = Request,
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The site has been published. Returns the time of publishing", body = String),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn publish(
+    auth: AuthUser,
+    payload: Json<Request>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&payload.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    auth.can_publish(&app_state)?;
+
+    let mut site = auth.owned_site(site_id, &app_state).await?;
+    let published = site.publish(&app_state).await?;
+    Ok(HttpResponse::Ok().body(published.time))
+}
+
+#[utoipa::path(
+    get,
+    path = "/site/export.pdf",
+    params(
+        ("id" = String, Query, description = "site id"),
+        ("page_size" = Option<String>, Query, description = "A3, A4, A5, Legal, Letter, Tabloid or Ledger. A4 by default"),
+        ("margin" = Option<String>, Query, description = "margin on every side of a page, in mm, cm, in or px. 10mm by default")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The draft of the site as a pdf", content_type = "application/pdf"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID, page size or margin"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn export_pdf(
+    req: HttpRequest,
+    auth: AuthUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<PdfQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Site ID not provided".into()))?;
+    let options = PdfOptions::new(query.page_size.as_deref(), query.margin.as_deref())?;
+
+    let site_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = auth.owned_site(site_id, &app_state).await?;
+    let file_name = format!("{}.pdf", site.metadata.name());
+    let file = site.export_pdf(options, &app_state).await?;
+    Ok(NamedFile::from_file(file, file_name)?.into_response(&req))
+}
+
+#[utoipa::path(
+    get,
+    path = "/site/status",
+    params(
+        ("id" = String, Query, description = "site id")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "Whether the site is published & live, if the draft has unpublished changes and the private url of the draft"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn status(
+    req: HttpRequest,
+    auth: AuthUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<SiteQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Site ID not provided".into()))?;
+
+    let site_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = auth.moderated_site(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(site.status(&app_state).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/site/revisions",
+    params(
+        ("id" = String, Query, description = "site id")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The revisions of this site, latest first", body = Vec<RevisionSummary>),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn list_revisions(
+    req: HttpRequest,
+    auth: AuthUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<SiteQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Site ID not provided".into()))?;
+
+    let site_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    auth.moderated_site(site_id, &app_state).await?;
+
+    let revisions = Revision::list(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/site/revisions/diff",
+    params(
+        ("site" = String, Query, description = "site id"),
+        ("from" = String, Query, description = "id of the older revision"),
+        ("to" = String, Query, description = "id of the newer revision")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "Unified diffs of the html, css, js & data between the two revisions"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site/revision ID"),
+        (status = 404, description = "revision doesn't exist"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn diff_revisions(
+    req: HttpRequest,
+    auth: AuthUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<DiffRequest>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Pass in the site & the two revisions to compare".into()))?;
+
+    let (site_id, from, to) = match (
+        ObjectId::parse_str(&query.site),
+        ObjectId::parse_str(&query.from),
+        ObjectId::parse_str(&query.to),
+    ) {
+        (Ok(site), Ok(from), Ok(to)) => (site, from, to),
+        _ => return Err(AppError::Validation("Invalid site/revision ID".into())),
     };
 
-    match Site::from(site_id, &app_state).await {
-        Ok(site) => match site.get_preview(!is_mobile).await {
-            Ok(file) => Ok(file.into_response(&req)),
-            Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
-        },
-        Err(_) => Ok(HttpResponse::NotFound().body("site with this id wasn't found"))
-    }
+    auth.moderated_site(site_id, &app_state).await?;
+
+    let from = Revision::from(from, site_id, &app_state).await?;
+    let to = Revision::from(to, site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(from.diff(&to)?))
 }
 
 #[utoipa::path(
     post,
-    path = "/site/preview_code",
-    request_body = CodePreviewRequest,
+    path = "/site/revisions/restore",
+    request_body = RestoreRequest,
+    security(
+        ("bearer_auth" = [])
+    ),
     responses(
-        (status = 200, description = "URL's resolving to the respective previews", body = Preview),
-        (status = 401, description = "Unauthorized user"),
+        (status = 200, description = "The id of the revision created by the restore"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site/revision ID"),
+        (status = 404, description = "revision doesn't exist"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn restore_revision(
+    auth: AuthUser,
+    payload: Json<RestoreRequest>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let (site_id, revision_id) = match (
+        ObjectId::parse_str(&payload.site_id),
+        ObjectId::parse_str(&payload.revision_id),
+    ) {
+        (Ok(site), Ok(revision)) => (site, revision),
+        _ => return Err(AppError::Validation("Invalid site/revision ID".into())),
+    };
+
+    auth.owned_site(site_id, &app_state).await?;
+
+    let revision = Revision::from(revision_id, site_id, &app_state).await?;
+    let id = revision.restore(auth.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().json(doc! { "revision_id": id.to_hex() }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/site/upgrade",
+    params(
+        ("id" = String, Query, description = "site id")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The version of the template the site is at & the latest one, unified diffs of the html, css & js between them, and the files that were edited on the site as well"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
         (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site with this id wasn't found, or it was created before templates were versioned"),
         (status = 500, description = "Internal error, contact admin.")
     ),
     tag = "site"
 )]
-async fn editor_preview(payload: Json<CodePreviewRequest>, app_state: web::Data<Arc<AppState>>) -> impl Responder {
-    match preview_code(&payload.html, &payload.css, &payload.js, &payload.data, &app_state).await {
-        Ok((mobile, desktop)) => HttpResponse::Ok().json(json!({"mobile": mobile, "desktop": desktop})),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string())
-    }
+async fn upgrade_status(
+    req: HttpRequest,
+    auth: AuthUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<SiteQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Site ID not provided".into()))?;
+
+    let site_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let site = auth.moderated_site(site_id, &app_state).await?;
+    let upgrade = Upgrade::new(&site, &app_state).await?;
+    Ok(HttpResponse::Ok().json(upgrade.summary()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/site/upgrade",
+    request_body = Request,
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The version of the template the site is now at. The upgrade is recorded as a new revision"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site with this id wasn't found, or it was created before templates were versioned"),
+        (status = 409, description = "Files changed in the template were edited on the site as well"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn upgrade(
+    auth: AuthUser,
+    payload: Json<Request>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&payload.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let mut site = auth.owned_site(site_id, &app_state).await?;
+    let upgrade = Upgrade::new(&site, &app_state).await?;
+    let version = upgrade.apply(&mut site, auth.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().json(json!({ "version": version })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/site/sync_profile",
+    request_body = Request,
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The selectors that were filled in from the profile. Anything edited on the site since the last sync is left as it is", body = Vec<String>),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not this user's site"),
+        (status = 400, description = "Invalid site ID"),
+        (status = 404, description = "site with this id wasn't found"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "site"
+)]
+async fn sync_profile(
+    auth: AuthUser,
+    payload: Json<Request>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&payload.site_id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    let mut site = auth.owned_site(site_id, &app_state).await?;
+    Ok(HttpResponse::Ok().json(site.sync_profile(auth.id(), &app_state).await?))
+}
+
+pub(crate) fn field_name(field: &Field) -> Result<String, AppError> {
+    field
+        .content_disposition()
+        .and_then(|content_disposition| content_disposition.get_name())
+        .map(String::from)
+        .ok_or_else(|| AppError::Validation("Every part of the form needs a name".into()))
+}
+
+fn field_string(chunk: &[u8]) -> Result<String, AppError> {
+    String::from_utf8(chunk.to_vec()).map_err(|_| AppError::Validation("Form fields should be valid utf-8".into()))
+}
+
+pub(crate) fn bad_field(e: MultipartError) -> AppError {
+    AppError::Validation(format!("Couldn't read the form: {}", e))
 }
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
@@ -491,9 +963,24 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
             .route("/rename", web::put().to(rename))
             .route("/asset", web::get().to(retrieve_resource))
             .route("/asset", web::put().to(save_resource))
+            .route("/asset", web::delete().to(delete_resource))
+            .route("/asset/rename", web::put().to(rename_resource))
+            .route("/assets", web::get().to(list_assets))
             .route("/source", web::post().to(get_source))
             .route("/source", web::put().to(change_source))
             .route("/preview", web::get().to(preview))
             .route("/preview_code", web::post().to(editor_preview))
+            .route("/preview_code/{job}", web::get().to(preview_status))
+            .route("/preview_code/{job}", web::delete().to(cancel_preview))
+            .route("/publish", web::post().to(publish))
+            .route("/status", web::get().to(status))
+            .route("/export.pdf", web::get().to(export_pdf))
+            .route("/revisions", web::get().to(list_revisions))
+            .route("/revisions/diff", web::get().to(diff_revisions))
+            .route("/revisions/restore", web::post().to(restore_revision))
+            .route("/upgrade", web::get().to(upgrade_status))
+            .route("/upgrade", web::post().to(upgrade))
+            .route("/sync_profile", web::post().to(sync_profile))
+            .route("/{id}", web::delete().to(delete_site))
     );
 }
@@ -1,4 +1,5 @@
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_multipart::Multipart;
+use actix_web::{web, HttpRequest, HttpResponse};
 use mongodb::bson::{doc, oid::ObjectId};
 use mongodb::options::FindOptions;
 use mongodb::Collection;
@@ -8,7 +9,12 @@ use utoipa::ToSchema;
 use std::sync::Arc;
 
 use crate::AppState;
-use crate::models::template::Template;
+use crate::error::AppError;
+use crate::handlers::auth::AuthUser;
+use crate::handlers::site::{bad_field, field_name};
+use crate::models::template::{Template, TemplateBundle, MAX_BUNDLE_SIZE};
+use crate::models::template_version::TemplateVersion;
+use crate::models::user::Role;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Query {
@@ -28,7 +34,8 @@ pub struct IdQuery {
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PreviewQuery {
     pub id: String,
-    pub wide: Option<bool>
+    pub wide: Option<bool>,
+    pub viewport: Option<String>,
 }
 
 #[utoipa::path(
@@ -41,12 +48,12 @@ pub struct PreviewQuery {
     ),
     tag = "template"
 )]
-async fn get_list(app_state: web::Data<Arc<AppState>>) -> impl Responder {
+async fn get_list(app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
     let templates: Collection<Template> = app_state.db.collection("templates");
-    let cursor = templates.find(None, None).await.unwrap();
-    let templates: Vec<Template> = cursor.try_collect().await.unwrap();
+    let cursor = templates.find(None, None).await?;
+    let templates: Vec<Template> = cursor.try_collect().await?;
 
-    HttpResponse::Ok().json(templates)
+    Ok(HttpResponse::Ok().json(templates))
 }
 
 #[utoipa::path(
@@ -60,12 +67,12 @@ async fn get_list(app_state: web::Data<Arc<AppState>>) -> impl Responder {
     ),
     tag = "template"
 )]
-async fn get_templates_by_author(author: web::Json<AuthorQuery>, app_state: web::Data<Arc<AppState>>) -> impl Responder {
+async fn get_templates_by_author(author: web::Json<AuthorQuery>, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
     let templates: Collection<Template> = app_state.db.collection("templates");
-    let cursor = templates.find(doc! { "author": &author.author }, None).await.unwrap();
-    let templates: Vec<Template> = cursor.try_collect().await.unwrap();
+    let cursor = templates.find(doc! { "author": &author.author }, None).await?;
+    let templates: Vec<Template> = cursor.try_collect().await?;
 
-    HttpResponse::Ok().json(templates)
+    Ok(HttpResponse::Ok().json(templates))
 }
 
 #[utoipa::path(
@@ -79,7 +86,7 @@ async fn get_templates_by_author(author: web::Json<AuthorQuery>, app_state: web:
     ),
     tag = "template"
 )]
-async fn search_templates(query: web::Json<Query>, app_state: web::Data<Arc<AppState>>) -> impl Responder {
+async fn search_templates(query: web::Json<Query>, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
     let templates: Collection<Template> = app_state.db.collection("templates");
     let search_query = doc! {
         "$or": [
@@ -87,10 +94,10 @@ async fn search_templates(query: web::Json<Query>, app_state: web::Data<Arc<AppS
             { "category": { "$regex": query.query.to_lowercase(), "$options": "i" } }
         ]
     };
-    let cursor = templates.find(search_query, None).await.unwrap();
-    let templates: Vec<Template> = cursor.try_collect().await.unwrap();
+    let cursor = templates.find(search_query, None).await?;
+    let templates: Vec<Template> = cursor.try_collect().await?;
 
-    HttpResponse::Ok().json(templates)
+    Ok(HttpResponse::Ok().json(templates))
 }
 
 #[utoipa::path(
@@ -105,19 +112,16 @@ async fn search_templates(query: web::Json<Query>, app_state: web::Data<Arc<AppS
     ),
     tag = "template"
 )]
-async fn get_template_by_id(id: web::Json<IdQuery>, app_state: web::Data<Arc<AppState>>) -> impl Responder {
+async fn get_template_by_id(id: web::Json<IdQuery>, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
     let templates: Collection<Template> = app_state.db.collection("templates");
-    let object_id = match ObjectId::parse_str(&id.id) {
-        Ok(oid) => oid,
-        Err(_) => return HttpResponse::BadRequest().body("Invalid ObjectId format"),
-    };
-    let template = match templates.find_one(doc! { "_id": object_id }, None).await {
-        Ok(Some(template)) => template,
-        Ok(None) => return HttpResponse::NotFound().body("Template not found"),
-        Err(_) => return HttpResponse::InternalServerError().body("Database error"),
-    };
+    let object_id = ObjectId::parse_str(&id.id)
+        .map_err(|_| AppError::Validation("Invalid ObjectId format".into()))?;
+    let template = templates
+        .find_one(doc! { "_id": object_id }, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Template not found".into()))?;
 
-    HttpResponse::Ok().json(template)
+    Ok(HttpResponse::Ok().json(template))
 }
 
 #[utoipa::path(
@@ -129,7 +133,7 @@ async fn get_template_by_id(id: web::Json<IdQuery>, app_state: web::Data<Arc<App
     ),
     tag = "template"
 )]
-async fn get_latest_templates(app_state: web::Data<Arc<AppState>>) -> impl Responder {
+async fn get_latest_templates(app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
     let templates: Collection<Template> = app_state.db.collection("templates");
     
     let options = FindOptions::builder()
@@ -137,10 +141,10 @@ async fn get_latest_templates(app_state: web::Data<Arc<AppState>>) -> impl Respo
         .limit(3)
         .build();
     
-    let cursor = templates.find(None, options).await.unwrap();
-    let templates: Vec<Template> = cursor.try_collect().await.unwrap();
+    let cursor = templates.find(None, options).await?;
+    let templates: Vec<Template> = cursor.try_collect().await?;
 
-    HttpResponse::Ok().json(templates)
+    Ok(HttpResponse::Ok().json(templates))
 }
 
 #[utoipa::path(
@@ -148,31 +152,161 @@ async fn get_latest_templates(app_state: web::Data<Arc<AppState>>) -> impl Respo
     path = "/template/preview",
     params(
         ("id" = String, Query, description = "Template ID"),
-        ("wide" = String, Query, description = "whether the preview should be wide (desktop view) or narrow (mobile view)")
+        ("viewport" = Option<String>, Query, description = "name of the viewport, one of those configured with PREVIEW_VIEWPORTS. mobile & desktop by default"),
+        ("wide" = Option<String>, Query, description = "whether the preview should be wide (desktop view) or narrow (mobile view), when no viewport is given")
     ),
     responses(
         (status = 200, description = "URL resolving to the respective preview"),
         (status = 401, description = "Unauthorized user"),
-        (status = 400, description = "Invalid template ID"),
+        (status = 400, description = "Invalid template ID or viewport"),
         (status = 404, description = "no preview available"),
         (status = 500, description = "Internal error, contact admin.")
     ),
     tag = "template"
 )]
-async fn get_preview(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> impl Responder {
-    let query = web::Query::<PreviewQuery>::from_query(req.query_string()).unwrap();
-    let is_mobile = match query.wide {
-        Some(wide) => wide,
-        None => false
+async fn get_preview(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<PreviewQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Template id not provided".into()))?;
+    let viewport = match (&query.viewport, query.wide) {
+        (Some(viewport), _) => viewport.as_str(),
+        (None, Some(true)) => "desktop",
+        (None, _) => "mobile",
     };
-    let object_id = match ObjectId::parse_str(&query.id) {
-        Ok(oid) => oid,
-        Err(_) => return HttpResponse::BadRequest().body("Invalid template id"),
-    };
-    match Template::get_preview(object_id, !is_mobile, &app_state.clone()).await {
-        Ok(file) => file.into_response(&req),
-        Err(_) => HttpResponse::NotFound().body("no preview available")
+    let object_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid template id".into()))?;
+    let file = Template::get_preview(object_id, viewport, &app_state.clone())
+        .await
+        .map_err(|e| match e {
+            AppError::Validation(_) => e,
+            _ => AppError::NotFound("no preview available".into()),
+        })?;
+    Ok(file.into_response(&req))
+}
+
+#[utoipa::path(
+    post,
+    path = "/template",
+    request_body(content = String, description = "Multipart form. A `bundle` field with the zipped template, or one field per file named after its path in the template (index.html, metadata.json, styles/styles.css, js/script.js & anything else it uses)", content_type = "multipart/form-data"),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 201, description = "The template has been published & its preview rendered", body = Template),
+        (status = 400, description = "The bundle is missing files, has an invalid metadata.json or is too big"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Needs the template_author role"),
+        (status = 409, description = "There's already a template with that name"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "template"
+)]
+async fn upload_template(
+    auth: AuthUser,
+    payload: Multipart,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    auth.require(Role::TemplateAuthor)?;
+
+    let bundle = read_bundle(payload).await?;
+    let template = Template::publish(bundle, auth.id(), None, &app_state).await?;
+    Ok(HttpResponse::Created().json(template))
+}
+
+#[utoipa::path(
+    put,
+    path = "/template/{id}",
+    request_body(content = String, description = "Multipart form, same as uploading a new template", content_type = "multipart/form-data"),
+    params(
+        ("id" = String, Path, description = "ID of the template")
+    ),
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The bundle has been published as the next version of the template", body = Template),
+        (status = 400, description = "Invalid template ID, or a bundle that's missing files, has an invalid metadata.json or is too big"),
+        (status = 401, description = "Expired/invalid access token"),
+        (status = 403, description = "Not the author of this template"),
+        (status = 404, description = "Template not found"),
+        (status = 409, description = "There's already another template with that name"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "template"
+)]
+async fn update_template(
+    auth: AuthUser,
+    id: web::Path<String>,
+    payload: Multipart,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    auth.require(Role::TemplateAuthor)?;
+
+    let object_id = ObjectId::parse_str(id.as_str())
+        .map_err(|_| AppError::Validation("Invalid template id".into()))?;
+    let templates: Collection<Template> = app_state.db.collection("templates");
+    let template = templates
+        .find_one(doc! { "_id": object_id }, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Template not found".into()))?;
+
+    // templates from the templates directory have no owner, so only admins can replace those
+    if template.owner != Some(auth.id()) {
+        auth.require(Role::Admin)
+            .map_err(|_| AppError::Forbidden("Only the author of this template can publish a new version".into()))?;
+    }
+
+    let bundle = read_bundle(payload).await?;
+    let template = Template::publish(bundle, auth.id(), Some(template), &app_state).await?;
+    Ok(HttpResponse::Ok().json(template))
+}
+
+#[utoipa::path(
+    get,
+    path = "/template/versions",
+    params(
+        ("id" = String, Query, description = "Template ID")
+    ),
+    responses(
+        (status = 200, description = "Every version of the template, latest first", body = Vec<TemplateVersionSummary>),
+        (status = 400, description = "Invalid template ID"),
+        (status = 500, description = "Internal error, contact admin.")
+    ),
+    tag = "template"
+)]
+async fn get_versions(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<IdQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Template id not provided".into()))?;
+    let object_id = ObjectId::parse_str(&query.id)
+        .map_err(|_| AppError::Validation("Invalid template id".into()))?;
+
+    Ok(HttpResponse::Ok().json(TemplateVersion::list(object_id, &app_state).await?))
+}
+
+// a field named bundle is a zipped template, any other field is a file at the path it's named after
+async fn read_bundle(mut payload: Multipart) -> Result<TemplateBundle, AppError> {
+    let mut bundle = TemplateBundle::new()?;
+
+    while let Some(mut field) = payload.try_next().await.map_err(bad_field)? {
+        let name = field_name(&field)?;
+        let mut content = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(bad_field)? {
+            content.extend_from_slice(&chunk);
+            if content.len() > MAX_BUNDLE_SIZE {
+                return Err(AppError::Validation(format!(
+                    "Templates can't be bigger than {} MB",
+                    MAX_BUNDLE_SIZE / (1024 * 1024)
+                )));
+            }
+        }
+
+        if name == "bundle" {
+            bundle.add_zip(&content)?;
+        } else {
+            bundle.add_file(&name, &content)?;
+        }
     }
+
+    Ok(bundle)
 }
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
@@ -184,5 +318,8 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
             .route("/by_id", web::get().to(get_template_by_id))
             .route("/latest", web::get().to(get_latest_templates))
             .route("/preview", web::get().to(get_preview))
+            .route("/versions", web::get().to(get_versions))
+            .route("", web::post().to(upload_template))
+            .route("/{id}", web::put().to(update_template))
     );
 }
@@ -3,100 +3,53 @@
 
 use actix_files::NamedFile;
 use actix_multipart::Multipart;
-use actix_web::http::Error;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse};
 use bcrypt::{hash, verify};
-use chrono::{Duration, Utc};
+use chrono::Utc;
 use futures::{StreamExt, TryStreamExt};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
 use mongodb::bson::{doc, oid::ObjectId, Bson};
 use mongodb::Collection;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::Arc;
 use utoipa::ToSchema;
 
+use crate::error::AppError;
+use crate::handlers::auth::{generate_token, AuthUser, MaybeAuthUser};
+use crate::models::session::Session;
 use crate::models::site::Site;
-use crate::models::user::{EditData, LoginData, SignupData, User, UserDataResponse};
+use crate::models::token::{Purpose, Token};
+use crate::services::paths;
+use crate::models::user::{DeleteAccountData, EditData, LoginData, PasswordData, ResetData, ResetRequestData, Role, SignupData, User, UserDataResponse, VerifyQuery};
 use crate::AppState;
 
 #[derive(Serialize, ToSchema)]
 pub struct LoginResponse {
     token: String,
+    refresh_token: String,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+// only used to document the multipart body of upload_dp
+#[allow(dead_code)]
 #[derive(Serialize, ToSchema)]
 pub struct FilePayload {
     file: PathBuf,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Claims {
-    sub: String,
-    exp: usize,
-}
-
-fn generate_token(user_id: &ObjectId, secret: &[u8]) -> String {
-    let expiration = Utc::now()
-        .checked_add_signed(Duration::seconds(24 * 3600)) // Token expires in 24 hours
-        .expect("valid timestamp")
-        .timestamp();
-
-    let claims = Claims {
-        sub: user_id.to_hex(),
-        exp: expiration as usize,
-    };
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret),
-    )
-    .unwrap()
-}
-
-pub fn verify_token(
-    token: &str,
-    secret: &[u8],
-) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret),
-        &Validation::default(),
-    )
-}
-
-pub async fn get_user_id_from_token(
-    req: &HttpRequest,
-    app_state: &web::Data<Arc<AppState>>,
-) -> Result<ObjectId, HttpResponse> {
-    let token = match req.headers().get("Authorization") {
-        Some(header_value) => {
-            let token_str = header_value.to_str().unwrap();
-            if token_str.starts_with("Bearer ") {
-                &token_str[7..]
-            } else {
-                return Err(
-                    HttpResponse::Unauthorized().body("Invalid authorization header format")
-                );
-            }
-        }
-        None => return Err(HttpResponse::Unauthorized().body("No authorization header provided")),
-    };
-
-    let token_data = match verify_token(token, &app_state.secret_key) {
-        Ok(data) => data,
-        Err(_) => {
-            return Err(HttpResponse::Unauthorized().body("Invalid token. Has likely expired."))
-        }
-    };
-
-    match ObjectId::parse_str(&token_data.claims.sub) {
-        Ok(id) => Ok(id),
-        Err(_) => Err(HttpResponse::Unauthorized().body("Invalid token data")),
-    }
+// starts a new session for the user & hands out its first pair of tokens
+async fn start_session(user_id: &ObjectId, app_state: &web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    let (session, refresh_token) = Session::create(*user_id, app_state).await?;
+    Ok(HttpResponse::Ok().json(LoginResponse {
+        token: generate_token(user_id, &session.id, &app_state.secret_key, app_state.access_token_ttl),
+        refresh_token,
+    }))
 }
 
 #[utoipa::path(
@@ -113,35 +66,33 @@ pub async fn get_user_id_from_token(
 async fn signup(
     data: web::Json<SignupData>,
     app_state: web::Data<Arc<AppState>>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     
     if vec![
-        "admin", "test", "zitefy", "login", "signup", "profile", "explore", "editor", "api", "404", "assets", "seo", ""
+        "admin", "test", "zitefy", "login", "signup", "profile", "explore", "editor", "api", "404", "assets", "seo", "draft", ""
     ].contains(&data.username.as_str()) {
-        return HttpResponse::Conflict().body("Sorry, this username is restricted.");
+        return Err(AppError::Conflict("Sorry, this username is restricted.".into()));
     }
 
     let users: Collection<User> = app_state.db.collection("users");
 
     if users
         .find_one(doc! {"email": &data.email}, None)
-        .await
-        .unwrap()
+        .await?
         .is_some()
     {
-        return HttpResponse::Conflict().body("Someone has already signed up with this email ID.");
+        return Err(AppError::Conflict("Someone has already signed up with this email ID.".into()));
     }
 
     if users
         .find_one(doc! {"username": &data.username}, None)
-        .await
-        .unwrap()
+        .await?
         .is_some()
     {
-        return HttpResponse::Conflict().body("Sorry, this username has either been already taken.");
+        return Err(AppError::Conflict("Sorry, this username has either been already taken.".into()));
     }
 
-    let hashed_password = hash(&data.password, 10).unwrap();
+    let hashed_password = hash(&data.password, 10)?;
     let new_user = User {
         _id: ObjectId::new(),
         name: "".to_string(),
@@ -156,12 +107,19 @@ async fn signup(
         pronouns: None,
         phone: None,
         image: None,
+        verified: false,
+        roles: vec![Role::User],
+        suspended: None,
     };
 
-    users.insert_one(new_user.clone(), None).await.unwrap();
-    let token = generate_token(&new_user._id, &app_state.secret_key);
+    users.insert_one(new_user.clone(), None).await?;
 
-    HttpResponse::Ok().json(json!({ "token": token }))
+    // the account is usable right away, so a failed mail shouldn't fail the signup. it can be resent.
+    if let Err(e) = send_verification(&new_user, &app_state).await {
+        eprintln!("Failed to send the verification mail: {}", e);
+    }
+
+    start_session(&new_user._id, &app_state).await
 }
 
 #[utoipa::path(
@@ -174,7 +132,7 @@ async fn signup(
     ),
     tag = "user"
 )]
-async fn login(data: web::Json<LoginData>, app_state: web::Data<Arc<AppState>>) -> impl Responder {
+async fn login(data: web::Json<LoginData>, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
     let users: Collection<User> = app_state.db.collection("users");
 
     let user = users
@@ -187,17 +145,246 @@ async fn login(data: web::Json<LoginData>, app_state: web::Data<Arc<AppState>>)
             },
             None,
         )
-        .await
-        .unwrap();
+        .await?;
 
     if let Some(user) = user {
-        if verify(&data.password, &user.passwd).unwrap() {
-            let token = generate_token(&user._id, &app_state.secret_key);
-            return HttpResponse::Ok().json(LoginResponse { token });
+        if verify(&data.password, &user.passwd).unwrap_or(false) {
+            if user.suspended.is_some() {
+                return Err(AppError::Forbidden("This account has been suspended".into()));
+            }
+            return start_session(&user._id, &app_state).await;
+        }
+    }
+
+    Err(AppError::Unauthorized("Invalid credentials".into()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/refresh",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "A new access token, along with the refresh token to use next time", body = LoginResponse),
+        (status = 401, description = "The refresh token is invalid, expired or has already been used")
+    ),
+    tag = "user"
+)]
+async fn refresh(data: web::Json<RefreshRequest>, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    let (session, refresh_token) = Session::rotate(&data.refresh_token, &app_state).await?;
+    Ok(HttpResponse::Ok().json(LoginResponse {
+        token: generate_token(&session.user, &session.id, &app_state.secret_key, app_state.access_token_ttl),
+        refresh_token,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/logout",
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "This device has been logged out", body = String),
+        (status = 401, description = "Invalid access token, likely expired."),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn logout(auth: AuthUser, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    Session::revoke(auth.session, &app_state).await?;
+    Ok(HttpResponse::Ok().body("Logged out"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/logout_all",
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "All devices have been logged out", body = String),
+        (status = 401, description = "Invalid access token, likely expired."),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn logout_all(auth: AuthUser, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    Session::revoke_all(auth.id(), &app_state).await?;
+    Ok(HttpResponse::Ok().body("Logged out of all devices"))
+}
+
+#[utoipa::path(
+    put,
+    path = "/user/password",
+    request_body = PasswordData,
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "Password changed. Other devices have been logged out", body = String),
+        (status = 401, description = "Invalid access token or wrong current password"),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn change_password(
+    auth: AuthUser,
+    data: web::Json<PasswordData>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    if !verify(&data.current_password, &auth.user.passwd).unwrap_or(false) {
+        return Err(AppError::Unauthorized("The current password is wrong".into()));
+    }
+
+    set_password(auth.id(), &data.new_password, &app_state).await?;
+    Session::revoke_others(auth.id(), auth.session, &app_state).await?;
+    Ok(HttpResponse::Ok().body("Password changed"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/reset/request",
+    request_body = ResetRequestData,
+    responses(
+        (status = 200, description = "If an account with this email exists, a reset link has been mailed to it", body = String),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn request_reset(
+    data: web::Json<ResetRequestData>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let users: Collection<User> = app_state.db.collection("users");
+
+    // the response is the same whether the account exists or not, so emails can't be fished out of here.
+    // that goes for mails that weren't sent, either because one just was or because sending failed.
+    if let Some(user) = users.find_one(doc! { "email": &data.email }, None).await? {
+        if let Some(time) = Token::last_issued(user._id, Purpose::PasswordReset, &app_state).await? {
+            if Utc::now().timestamp() - time < app_state.reset_request_interval {
+                return Ok(HttpResponse::Ok().body(RESET_REQUESTED));
+            }
+        }
+
+        let token = Token::issue(user._id, Purpose::PasswordReset, app_state.reset_token_ttl, &app_state).await?;
+
+        let body = format!(
+            "Hey {},\n\nSomeone asked to reset the password of your zitefy account. If it was you, open this link to set a new one:\n\n{}/reset?token={}\n\nThe link expires in {} minutes. If it wasn't you, you can ignore this mail.",
+            user.username, app_state.portal_url, token, app_state.reset_token_ttl / 60
+        );
+        if let Err(e) = app_state.mailer.send(&user.email, "Reset your zitefy password", &body).await {
+            eprintln!("Failed to send the reset mail to {}: {}", user._id, e);
+        }
+    }
+
+    Ok(HttpResponse::Ok().body(RESET_REQUESTED))
+}
+
+const RESET_REQUESTED: &str = "If an account with this email exists, a reset link has been sent to it.";
+
+#[utoipa::path(
+    post,
+    path = "/user/reset",
+    request_body = ResetData,
+    responses(
+        (status = 200, description = "Password changed. All devices have been logged out", body = String),
+        (status = 400, description = "The reset link is invalid or has expired"),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn reset_password(
+    data: web::Json<ResetData>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = Token::redeem(&data.token, Purpose::PasswordReset, &app_state).await?;
+
+    set_password(user_id, &data.password, &app_state).await?;
+    Session::revoke_all(user_id, &app_state).await?;
+    Ok(HttpResponse::Ok().body("Password changed, login again"))
+}
+
+async fn send_verification(user: &User, app_state: &web::Data<Arc<AppState>>) -> Result<(), AppError> {
+    let token = Token::issue(user._id, Purpose::EmailVerification, app_state.verify_token_ttl, app_state).await?;
+    let body = format!(
+        "Hey {},\n\nWelcome to zitefy! Open this link to verify your email:\n\n{}/verify?token={}\n\nThe link expires in {} hours.",
+        user.username, app_state.portal_url, token, app_state.verify_token_ttl / 3600
+    );
+    app_state.mailer
+        .send(&user.email, "Verify your email on zitefy", &body)
+        .await
+        .map_err(|e| AppError::Upstream(format!("Failed to send the verification mail: {}", e)))?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/verify",
+    params(
+        ("token" = String, Query, description = "the token from the verification mail")
+    ),
+    responses(
+        (status = 200, description = "The email has been verified", body = String),
+        (status = 400, description = "The verification link is invalid or has expired"),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn verify_email(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<VerifyQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Verification token not provided".into()))?;
+
+    let user_id = Token::redeem(&query.token, Purpose::EmailVerification, &app_state).await?;
+
+    let users: Collection<User> = app_state.db.collection("users");
+    users.update_one(doc! { "_id": user_id }, doc! { "$set": { "verified": true } }, None).await?;
+    Ok(HttpResponse::Ok().body("Email verified"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/verify/resend",
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "A new verification link has been mailed", body = String),
+        (status = 400, description = "The email is already verified"),
+        (status = 401, description = "Invalid access token, likely expired."),
+        (status = 429, description = "A link was sent too recently, try again in a bit"),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn resend_verification(auth: AuthUser, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    let user = auth.user;
+
+    if user.verified {
+        return Err(AppError::Validation("This email is already verified".into()));
+    }
+
+    if let Some(time) = Token::last_issued(user._id, Purpose::EmailVerification, &app_state).await? {
+        if Utc::now().timestamp() - time < app_state.verify_resend_interval {
+            return Err(AppError::RateLimited("A verification mail was sent just now, try again in a bit".into()));
         }
     }
 
-    HttpResponse::Unauthorized().body("Invalid credentials")
+    send_verification(&user, &app_state).await?;
+    Ok(HttpResponse::Ok().body("Verification mail sent"))
+}
+
+async fn set_password(
+    user_id: ObjectId,
+    password: &str,
+    app_state: &web::Data<Arc<AppState>>,
+) -> Result<(), AppError> {
+    let hashed_password = hash(password, 10)?;
+
+    let users: Collection<User> = app_state.db.collection("users");
+    users
+        .update_one(doc! { "_id": user_id }, doc! { "$set": { "passwd": hashed_password } }, None)
+        .await?;
+    Ok(())
 }
 
 macro_rules! update_field {
@@ -233,18 +420,12 @@ macro_rules! update_links {
     tag = "user"
 )]
 async fn edit(
-    req: HttpRequest,
+    auth: AuthUser,
     data: web::Json<EditData>,
     app_state: web::Data<Arc<AppState>>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     let users: Collection<User> = app_state.db.collection("users");
-
-    // there might be a way to refactor this better. this line repeats everywhere
-    // get_user_id_from_token() was refactored, but i still think there's more room.
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(response) => return response,
-    };
+    let user_id = auth.id();
 
     let mut update_doc = doc! {};
 
@@ -257,10 +438,11 @@ async fn edit(
 
     users
         .update_one(doc! { "_id": user_id }, doc! { "$set": update_doc }, None)
-        .await
-        .unwrap();
+        .await?;
 
-    HttpResponse::Ok().body("User updated successfully")
+    app_state.republisher.schedule_for_user(user_id, app_state.get_ref().clone()).await;
+
+    Ok(HttpResponse::Ok().body("User updated successfully"))
 }
 
 #[utoipa::path(
@@ -279,54 +461,30 @@ async fn edit(
     tag = "user"
 )]
 async fn upload_dp(
-    req: HttpRequest,
+    auth: AuthUser,
     mut payload: Multipart,
     app_state: web::Data<Arc<AppState>>,
-) -> impl Responder {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(response) => return response,
-    };
+) -> Result<HttpResponse, AppError> {
+    let user_id = auth.id();
 
-    let user_dir = format!(
-        "{}/.zitefy/users/{}",
-        std::env::var("HOME").unwrap(),
-        user_id
-    );
-    if let Err(e) = fs::create_dir_all(&user_dir) {
-        eprintln!("Failed to create directory: {}", e);
-        return HttpResponse::InternalServerError().body("Failed to create directory");
-    }
+    let user_dir = User::dir(&user_id)?;
+    fs::create_dir_all(&user_dir)?;
 
-    let dp_path = format!("{}/dp.png", user_dir);
+    let dp_path = paths::join(&user_dir, "dp.png")?;
 
     let mut file_written = false;
 
     while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_disposition = field.content_disposition().unwrap();
-
-        if let Some(_filename) = content_disposition.get_filename() {
-            let filepath = Path::new(&dp_path);
-            let mut f = match File::create(filepath) {
-                Ok(file) => file,
-                Err(e) => {
-                    eprintln!("Failed to create file: {}", e);
-                    return HttpResponse::InternalServerError().body("Failed to create file");
-                }
-            };
+        let has_filename = field
+            .content_disposition()
+            .is_some_and(|content_disposition| content_disposition.get_filename().is_some());
+
+        if has_filename {
+            let mut f = File::create(&dp_path)?;
 
             while let Some(chunk) = field.next().await {
-                let data = match chunk {
-                    Ok(data) => data,
-                    Err(e) => {
-                        eprintln!("Error reading chunk: {}", e);
-                        return HttpResponse::BadRequest().body("Error reading file chunk");
-                    }
-                };
-                if let Err(e) = f.write_all(&data) {
-                    eprintln!("Error writing to file: {}", e);
-                    return HttpResponse::InternalServerError().body("Error writing to file");
-                }
+                let data = chunk.map_err(|_| AppError::Validation("Error reading file chunk".into()))?;
+                f.write_all(&data)?;
                 file_written = true;
             }
         } else {
@@ -335,36 +493,29 @@ async fn upload_dp(
     }
     
     if !file_written {
-        eprintln!("No file was written");
-        return HttpResponse::BadRequest().body("No file was uploaded");
+        return Err(AppError::Validation("No file was uploaded".into()));
     }
 
     let users: Collection<User> = app_state.db.collection("users");
-    match users
+    users
         .update_one(
             doc! { "_id": user_id },
-            doc! { "$set": { "image": dp_path.clone() } },
+            doc! { "$set": { "image": dp_path.to_string_lossy().into_owned() } },
             None,
         )
-        .await
-    {
-        Ok(_) => HttpResponse::Ok().body("Profile picture uploaded successfully"),
-        Err(e) => {
-            eprintln!("Failed to update user document: {}", e);
-            HttpResponse::InternalServerError().body("Failed to update user document")
-        }
-    }
+        .await?;
+    Ok(HttpResponse::Ok().body("Profile picture uploaded successfully"))
 }
 
 #[derive(serde::Deserialize)]
 struct ProfilePictureQuery {
-    username: String,
+    username: Option<String>,
 }
 #[utoipa::path(
     get,
     path = "/user/dp",
     params(
-        ("username" = String, Query, description = "zitefy username of the user")
+        ("username" = Option<String>, Query, description = "zitefy username of the user. defaults to the logged in user")
     ),
     responses(
         (status = 200, description = "image found", content_type = "image/*"),
@@ -374,29 +525,32 @@ struct ProfilePictureQuery {
 )]
 async fn get_profile_picture(
     req: HttpRequest,
+    auth: MaybeAuthUser,
     app_state: web::Data<Arc<AppState>>,
-) -> impl Responder {
-    let query = web::Query::<ProfilePictureQuery>::from_query(req.query_string()).unwrap();
-
-    let users: mongodb::Collection<User> = app_state.db.collection("users");
-    let user = users
-        .find_one(doc! { "username": &query.username }, None)
-        .await
-        .unwrap();
+) -> Result<HttpResponse, AppError> {
+    let query = web::Query::<ProfilePictureQuery>::from_query(req.query_string())
+        .map_err(|_| AppError::Validation("Invalid query".into()))?;
+
+    let user = match (&query.username, auth.0) {
+        (Some(username), _) => {
+            let users: mongodb::Collection<User> = app_state.db.collection("users");
+            users
+                .find_one(doc! { "username": username }, None)
+                .await?
+                .ok_or_else(|| AppError::NotFound("User not found".into()))?
+        },
+        (None, Some(auth)) => auth.user,
+        (None, None) => return Err(AppError::Validation("Username not provided".into())),
+    };
 
-    if let Some(user) = user {
-        if let Some(image_path) = user.image {
-            let path: PathBuf = image_path.into();
-            return match NamedFile::open(path) {
-                Ok(file) => file.into_response(&req),
-                Err(_) => HttpResponse::NotFound().body("Image not found"),
-            };
-        } else {
-            return HttpResponse::NotFound().body("No profile picture set");
-        }
-    } else {
-        HttpResponse::NotFound().body("User not found")
-    }
+    let path: PathBuf = user.image
+        .ok_or_else(|| AppError::NotFound("No profile picture set".into()))?
+        .into();
+    // only ever served from the users' own directories
+    let path = paths::contained(&paths::zitefy_dir()?.join("users"), &path)
+        .map_err(|_| AppError::NotFound("Image not found".into()))?;
+    let file = NamedFile::open(path).map_err(|_| AppError::NotFound("Image not found".into()))?;
+    Ok(file.into_response(&req))
 }
 
 #[utoipa::path(
@@ -415,31 +569,23 @@ async fn get_profile_picture(
     ),
     tag = "user"
 )]
-async fn get_data(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> impl Responder {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(response) => return response,
+async fn get_data(auth: AuthUser) -> Result<HttpResponse, AppError> {
+    let user = auth.user;
+
+    let response = UserDataResponse {
+        name: Some(user.name),
+        username: user.username,
+        email: user.email,
+        active: user.active,
+        bio: user.bio,
+        links: user.links,
+        pronouns: user.pronouns,
+        phone: user.phone,
+        dob: user.dob,
+        verified: user.verified,
+        roles: user.roles,
     };
-
-    let users: Collection<User> = app_state.db.collection("users");
-    let user = users.find_one(doc! { "_id": user_id }, None).await.unwrap();
-
-    if let Some(user) = user {
-        let response = UserDataResponse {
-            name: Some(user.name),
-            username: user.username,
-            email: user.email,
-            active: user.active,
-            bio: user.bio,
-            links: user.links,
-            pronouns: user.pronouns,
-            phone: user.phone,
-            dob: user.dob
-        };
-        HttpResponse::Ok().json(response)
-    } else {
-        HttpResponse::NotFound().body("User not found")
-    }
+    Ok(HttpResponse::Ok().json(response))
 }
 
 #[derive(serde::Deserialize, ToSchema)]
@@ -456,20 +602,11 @@ struct SiteRequest {
     tag = "user"
 )]
 async fn get_sites(
-    req: HttpRequest,
+    auth: AuthUser,
     app_state: web::Data<Arc<AppState>>,
-) -> Result<HttpResponse, Error> {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(response) => return Ok(response),
-    };
-
-    let response = match Site::get_by_user(user_id, &app_state.clone()).await {
-        Ok(sites) => HttpResponse::Ok().json(sites),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
-    };
-
-    Ok(response)
+) -> Result<HttpResponse, AppError> {
+    let sites = Site::get_by_user(auth.id(), &app_state.clone()).await?;
+    Ok(HttpResponse::Ok().json(sites))
 }
 
 #[utoipa::path(
@@ -482,45 +619,90 @@ async fn get_sites(
     responses(
         (status = 200, description = "The site has been activated", body = String),
         (status = 400, description = "Invalid site ID"),
-        (status = 401, description = "Not this user's site"),
+        (status = 401, description = "Invalid access token, likely expired."),
+        (status = 403, description = "Not this user's site, or the email isn't verified yet"),
         (status = 404, description = "No site with this ID"),
         (status = 500, description = "Internal error, contact admin")
     ),
     tag = "user"
 )]
 async fn set_active(
-    req: HttpRequest,
+    auth: AuthUser,
     payload: web::Json<SiteRequest>,
     app_state: web::Data<Arc<AppState>>,
-) -> impl Responder {
-    let user_id = match get_user_id_from_token(&req, &app_state).await {
-        Ok(id) => id,
-        Err(response) => return response,
-    };
+) -> Result<HttpResponse, AppError> {
+    let site_id = ObjectId::parse_str(&payload.id)
+        .map_err(|_| AppError::Validation("Invalid site ID".into()))?;
+
+    auth.can_publish(&app_state)?;
+    let mut site = auth.owned_site(site_id, &app_state).await?;
+    if site.taken_down.is_some() {
+        return Err(AppError::Forbidden("This site has been taken down by an admin".into()));
+    }
 
-    let site_id = match ObjectId::parse_str(&payload.id) {
-        Ok(oid) => oid,
-        Err(_) => return HttpResponse::BadRequest().body("Invalid site ID"),
+    // a site that was never published goes live with its current draft
+    let html = match site.published.clone() {
+        Some(published) => published.html,
+        None => site.publish(&app_state).await?.html,
     };
 
     let users: Collection<User> = app_state.db.collection("users");
-    if !Site::is_owner(site_id, user_id, &app_state).await.unwrap() {
-        HttpResponse::Unauthorized().body("This user isn't authorized to use this site")
-    } else {
-        match Site::from(site_id, &app_state).await {
-            Ok(site) => {
-                match users.update_one(
-                    doc! { "_id": user_id },
-                    doc! { "$set": { "active": site.id, "quick_response": Some(site.get_html().await.unwrap()) } },
-                    None
-                ).await {
-                    Ok(_) => HttpResponse::Ok().body("Set the active site!"),
-                    Err(_) => HttpResponse::InternalServerError().body("Failed to update active site"),
-                }
-            },
-            Err(_) => HttpResponse::NotFound().body("A site with this ID doesn't exist")
-        }
+    users.update_one(
+        doc! { "_id": auth.id() },
+        doc! { "$set": { "active": site.id, "quick_response": html } },
+        None
+    ).await?;
+    Ok(HttpResponse::Ok().body("Set the active site!"))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user",
+    request_body = DeleteAccountData,
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "The account & everything in it has been deleted", body = String),
+        (status = 401, description = "Invalid access token or wrong password"),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn delete_account(
+    auth: AuthUser,
+    data: web::Json<DeleteAccountData>,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, AppError> {
+    let user = auth.user;
+
+    if !verify(&data.password, &user.passwd).unwrap_or(false) {
+        return Err(AppError::Unauthorized("Wrong password".into()));
     }
+
+    user.delete(&app_state).await?;
+    Ok(HttpResponse::Ok().body("Account deleted"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/export",
+    security(
+        ("bearer_auth" = [])
+    ),
+    responses(
+        (status = 200, description = "A zip of the user's data, sites & resources", content_type = "application/zip"),
+        (status = 401, description = "Invalid access token, likely expired."),
+        (status = 500, description = "Internal error, contact admin")
+    ),
+    tag = "user"
+)]
+async fn export(req: HttpRequest, auth: AuthUser, app_state: web::Data<Arc<AppState>>) -> Result<HttpResponse, AppError> {
+    let user = auth.user;
+
+    let file_name = format!("zitefy-{}.zip", user.username);
+    let file = user.export(&app_state).await?;
+    Ok(NamedFile::from_file(file, file_name)?.into_response(&req))
 }
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
@@ -528,11 +710,21 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
         web::scope("/user")
             .route("/signup", web::post().to(signup))
             .route("/login", web::post().to(login))
+            .route("/refresh", web::post().to(refresh))
+            .route("/logout", web::post().to(logout))
+            .route("/logout_all", web::post().to(logout_all))
+            .route("/password", web::put().to(change_password))
+            .route("/reset/request", web::post().to(request_reset))
+            .route("/reset", web::post().to(reset_password))
+            .route("/verify", web::get().to(verify_email))
+            .route("/verify/resend", web::post().to(resend_verification))
             .route("/edit", web::put().to(edit))
             .route("/upload_dp", web::put().to(upload_dp))
             .route("/activate", web::post().to(set_active))
             .route("/data", web::get().to(get_data))
             .route("/dp", web::get().to(get_profile_picture))
-            .route("/sites", web::get().to(get_sites)),
+            .route("/sites", web::get().to(get_sites))
+            .route("/export", web::get().to(export))
+            .route("", web::delete().to(delete_account)),
     );
 }
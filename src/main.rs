@@ -4,23 +4,32 @@ use actix_files::Files;
 use actix_web::http::header;
 use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use dotenv::dotenv;
-use env_logger;
 use mongodb::{options::ClientOptions, Client, Database};
-use std::{sync::Arc, env};
+use std::{sync::Arc, env, time::Duration};
 use tokio::task;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
-use reqwest;
 
 mod config;
+mod error;
 mod handlers;
 mod models;
 pub mod server;
 mod services;
 
 use crate::server::domain_server;
-use handlers::{user::LoginResponse, proxy_anthropic};
-use models::user::{EditData, LoginData, SignupData, UserDataResponse};
+use error::AppError;
+use handlers::{admin::ModerationRequest, user::{LoginResponse, RefreshRequest}, proxy_anthropic};
+use models::user::{DeleteAccountData, EditData, LoginData, PasswordData, ResetData, ResetRequestData, Role, SignupData, UserDataResponse, UserSummary};
+use models::moderation::Moderation;
+use models::schema::{Field, FieldType};
+use models::site::Asset;
+use models::template_version::{TemplateRef, TemplateVersionSummary};
+use services::mail::{self, Mailer};
+use services::paths;
+use services::preview::{renderer_from_config, PreviewQueue, PreviewSettings, PreviewStatus};
+use services::preview_cache::PreviewCache;
+use services::republish::Republisher;
 use services::tempfiles::TempFileService;
 
 // this is very cumbersome, has to be changed.
@@ -30,35 +39,74 @@ use services::tempfiles::TempFileService;
     paths(
         handlers::user::signup,
         handlers::user::login,
+        handlers::user::refresh,
+        handlers::user::logout,
+        handlers::user::logout_all,
+        handlers::user::change_password,
+        handlers::user::request_reset,
+        handlers::user::reset_password,
+        handlers::user::verify_email,
+        handlers::user::resend_verification,
         handlers::user::edit,
         handlers::user::upload_dp,
         handlers::user::get_data,
         handlers::user::get_profile_picture,
         handlers::user::get_sites,
         handlers::user::set_active,
+        handlers::user::delete_account,
+        handlers::user::export,
         handlers::site::new_site,
         handlers::site::view_site,
         handlers::site::save_site,
         handlers::site::retrieve_resource,
         handlers::site::save_resource,
+        handlers::site::list_assets,
+        handlers::site::delete_resource,
+        handlers::site::rename_resource,
         handlers::site::get_source,
         handlers::site::change_source,
         handlers::site::preview,
+        handlers::site::editor_preview,
+        handlers::site::preview_status,
+        handlers::site::cancel_preview,
+        handlers::site::delete_site,
+        handlers::site::publish,
+        handlers::site::status,
+        handlers::site::export_pdf,
+        handlers::site::list_revisions,
+        handlers::site::diff_revisions,
+        handlers::site::restore_revision,
+        handlers::site::upgrade_status,
+        handlers::site::upgrade,
+        handlers::site::sync_profile,
         handlers::template::get_list,
         handlers::template::get_templates_by_author,
         handlers::template::search_templates,
         handlers::template::get_latest_templates,
         handlers::template::get_template_by_id,
         handlers::template::get_preview,
+        handlers::template::upload_template,
+        handlers::template::update_template,
+        handlers::template::get_versions,
+        handlers::admin::list_users,
+        handlers::admin::suspend_user,
+        handlers::admin::unsuspend_user,
+        handlers::admin::delete_user,
+        handlers::admin::take_down_site,
+        handlers::admin::restore_site,
+        handlers::admin::regenerate_preview,
+        handlers::admin::sync_all_templates,
+        handlers::admin::sync_template,
         serve_preview_image
     ),
     components(
-        schemas(LoginData, UserDataResponse, SignupData, EditData, LoginResponse),
+        schemas(LoginData, UserDataResponse, SignupData, EditData, DeleteAccountData, LoginResponse, RefreshRequest, PasswordData, ResetRequestData, ResetData, Role, UserSummary, Moderation, ModerationRequest, TemplateRef, TemplateVersionSummary, Field, FieldType, Asset, PreviewStatus),
     ),
     tags(
         (name = "user", description = "User management endpoints"),
         (name = "site", description = "Site management endpoints"),
         (name = "template", description = "Template management endpoints"),
+        (name = "admin", description = "Moderation endpoints, only for admins"),
         (name = "proxy", description = "Proxy to external servers with CORS disallowed")
     )
 )]
@@ -70,6 +118,22 @@ struct AppState {
     tempfiles: TempFileService,
     client: reqwest::Client,
     anthropic_token: String,
+    revision_retention: usize,
+    republisher: Republisher,
+    previews: PreviewQueue,
+    access_token_ttl: i64,
+    refresh_token_ttl: i64,
+    reset_token_ttl: i64,
+    verify_token_ttl: i64,
+    verify_resend_interval: i64,
+    reset_request_interval: i64,
+    require_verified_email: bool,
+    portal_url: String,
+    api_url: String,
+    site_quota: u64,
+    user_quota: u64,
+    asset_limit: usize,
+    mailer: Box<dyn Mailer>,
 }
 
 #[utoipa::path(
@@ -111,12 +175,44 @@ async fn main() -> std::io::Result<()> {
     let client = Client::with_options(client_options).unwrap();
     let db = client.database("zitefy");
 
+    let tempfiles = TempFileService::new();
+    let cache_dir = paths::zitefy_dir().expect("HOME must be set").join("previews");
+    let previews = PreviewQueue::new(
+        config.preview_workers,
+        config.preview_queue,
+        Duration::from_secs(config.preview_timeout),
+        PreviewSettings {
+            viewports: config.preview_viewports.clone(),
+            format: config.preview_format,
+            quality: config.preview_quality,
+        },
+        renderer_from_config(&config),
+        PreviewCache::new(cache_dir, config.preview_cache_size),
+        tempfiles.clone(),
+    );
+
     let app_state = Arc::new(AppState {
         db: db.clone(),
         secret_key: config.secret_key.clone(),
-        tempfiles: TempFileService::new(),
+        tempfiles,
         client: reqwest::Client::new(),
-        anthropic_token: config.anthropic_token
+        anthropic_token: config.anthropic_token.clone(),
+        revision_retention: config.revision_retention,
+        republisher: Republisher::new(Duration::from_secs(config.republish_delay)),
+        previews,
+        access_token_ttl: config.access_token_ttl,
+        refresh_token_ttl: config.refresh_token_ttl,
+        reset_token_ttl: config.reset_token_ttl,
+        verify_token_ttl: config.verify_token_ttl,
+        verify_resend_interval: config.verify_resend_interval,
+        reset_request_interval: config.reset_request_interval,
+        require_verified_email: config.require_verified_email,
+        portal_url: config.portal_url.clone(),
+        api_url: config.api_url.clone(),
+        site_quota: config.site_quota,
+        user_quota: config.user_quota,
+        asset_limit: config.asset_limit,
+        mailer: mail::from_config(&config),
     });
 
     // Start the background task for monitoring the templates directory
@@ -141,11 +237,14 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(cors)
             .app_data(web::Data::new(app_state.clone()))
+            // malformed json bodies get the same error envelope as everything else
+            .app_data(web::JsonConfig::default().error_handler(|e, _| AppError::Validation(e.to_string()).into()))
             .service(serve_preview_image)
             .service(proxy_anthropic)
             .configure(handlers::user::init_routes)
             .configure(handlers::template::init_routes)
             .configure(handlers::site::init_routes)
+            .configure(handlers::admin::init_routes)
             .service(
                 SwaggerUi::new("/docs/{_:.*}").url("/api-docs/openapi.json", ApiDoc::openapi()),
             )
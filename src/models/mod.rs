@@ -1,3 +1,9 @@
 pub mod user;
 pub mod template;
-pub mod site;
\ No newline at end of file
+pub mod site;
+pub mod revision;
+pub mod session;
+pub mod token;
+pub mod moderation;
+pub mod template_version;
+pub mod schema;
\ No newline at end of file
@@ -0,0 +1,12 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// why, when & by which admin a user was suspended or a site was taken down.
+// kept on the document so that it can be shown to the owner & undone later.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Moderation {
+    pub reason: String,
+    pub time: String,
+    pub by: ObjectId,
+}
@@ -0,0 +1,178 @@
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, to_bson};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use similar::TextDiff;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::models::site::{read_to_string, Data, Site};
+use crate::services::paths;
+use crate::error::AppError;
+use crate::AppState;
+
+// an immutable snapshot of a site, taken every time its source or data is saved.
+// the source files are stored inline since they're small & we never edit them after the fact.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Revision {
+    #[serde(rename = "_id")]
+    pub id: Option<ObjectId>,
+    pub site: ObjectId,
+    pub author: ObjectId,
+    pub time: String,
+    pub html: String,
+    pub css: String,
+    pub js: String,
+    pub data: Vec<Data>,
+}
+
+// what gets listed, without the source
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RevisionSummary {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub author: ObjectId,
+    pub time: String,
+}
+
+impl Revision {
+    // snapshots the current state of the site and drops the revisions that fall out of retention
+    pub async fn create(
+        site: &Site,
+        author: ObjectId,
+        app_state: &Arc<AppState>,
+    ) -> Result<ObjectId, AppError> {
+        let site_id = site.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+        let base = Path::new(&site.path);
+
+        let revision = Revision {
+            id: Some(ObjectId::new()),
+            site: site_id,
+            author,
+            time: Utc::now().to_rfc3339(),
+            html: read_to_string(&base.join("index.html")).unwrap_or_default(),
+            css: read_to_string(&base.join("styles").join("styles.css")).unwrap_or_default(),
+            js: read_to_string(&base.join("js").join("script.js")).unwrap_or_default(),
+            data: site.data.clone(),
+        };
+
+        let revisions: Collection<Revision> = app_state.db.collection("revisions");
+        revisions.insert_one(&revision, None).await?;
+
+        Revision::prune(site_id, app_state).await?;
+        Ok(revision.id.unwrap())
+    }
+
+    // keeps only the latest `revision_retention` revisions of a site
+    async fn prune(site_id: ObjectId, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let revisions: Collection<RevisionSummary> = app_state.db.collection("revisions");
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": -1 })
+            .skip(app_state.revision_retention as u64)
+            .projection(doc! { "_id": 1, "author": 1, "time": 1 })
+            .build();
+
+        let stale: Vec<RevisionSummary> = revisions
+            .find(doc! { "site": site_id }, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        if !stale.is_empty() {
+            let ids: Vec<ObjectId> = stale.into_iter().map(|revision| revision.id).collect();
+            revisions.delete_many(doc! { "_id": { "$in": ids } }, None).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn from(
+        id: ObjectId,
+        site_id: ObjectId,
+        app_state: &Arc<AppState>,
+    ) -> Result<Self, AppError> {
+        let revisions: Collection<Revision> = app_state.db.collection("revisions");
+        revisions
+            .find_one(doc! { "_id": id, "site": site_id }, None)
+            .await?
+            .ok_or_else(|| AppError::NotFound("revision not found".into()))
+    }
+
+    // latest first
+    pub async fn list(
+        site_id: ObjectId,
+        app_state: &Arc<AppState>,
+    ) -> Result<Vec<RevisionSummary>, AppError> {
+        let revisions: Collection<RevisionSummary> = app_state.db.collection("revisions");
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": -1 })
+            .projection(doc! { "_id": 1, "author": 1, "time": 1 })
+            .build();
+
+        Ok(revisions.find(doc! { "site": site_id }, options).await?.try_collect().await?)
+    }
+
+    // every revision with its source, for exports
+    pub async fn list_full(
+        site_id: ObjectId,
+        app_state: &Arc<AppState>,
+    ) -> Result<Vec<Revision>, AppError> {
+        let revisions: Collection<Revision> = app_state.db.collection("revisions");
+        let options = FindOptions::builder().sort(doc! { "_id": -1 }).build();
+        Ok(revisions.find(doc! { "site": site_id }, options).await?.try_collect().await?)
+    }
+
+    // unified diffs of every source file & the data, going from self to other
+    pub fn diff(&self, other: &Revision) -> Result<serde_json::Value, AppError> {
+        // never the all-zero default id, which would look like a real revision
+        #[allow(clippy::unwrap_or_default)]
+        let (from, to) = (self.id.unwrap_or_else(ObjectId::new).to_hex(), other.id.unwrap_or_else(ObjectId::new).to_hex());
+        let unified = |old: &str, new: &str| {
+            TextDiff::from_lines(old, new)
+                .unified_diff()
+                .header(&from, &to)
+                .to_string()
+        };
+
+        Ok(json!({
+            "html": unified(&self.html, &other.html),
+            "css": unified(&self.css, &other.css),
+            "js": unified(&self.js, &other.js),
+            "data": unified(
+                &serde_json::to_string_pretty(&self.data)?,
+                &serde_json::to_string_pretty(&other.data)?
+            ),
+        }))
+    }
+
+    // writes this revision back to the site. the restore itself is recorded as a new revision.
+    pub async fn restore(
+        self,
+        author: ObjectId,
+        app_state: &Arc<AppState>,
+    ) -> Result<ObjectId, AppError> {
+        let mut site = Site::from(self.site, app_state).await?;
+        let base = Path::new(&site.path);
+
+        File::create(paths::join(base, "index.html")?)?.write_all(self.html.as_bytes())?;
+        File::create(paths::join(base, "styles/styles.css")?)?.write_all(self.css.as_bytes())?;
+        File::create(paths::join(base, "js/script.js")?)?.write_all(self.js.as_bytes())?;
+
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(
+            doc! { "_id": self.site },
+            doc! { "$set": { "data": to_bson(&self.data)? } },
+            None
+        ).await?;
+        site.data = self.data;
+
+        let id = Revision::create(&site, author, app_state).await?;
+        site.update_preview(app_state).await?;
+        Ok(id)
+    }
+}
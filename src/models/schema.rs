@@ -0,0 +1,247 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+use crate::error::{AppError, FieldError};
+use crate::models::site::Data;
+
+// the kind of value a field holds, which decides how it's checked
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    #[default]
+    Text,
+    Url,
+    Email,
+    Phone,
+    Date,
+    Image,
+    // repeated items, each with the data of the nested fields
+    Group,
+    // repeated plain values
+    List,
+}
+
+// a piece of data a template expects, declared under "fields" in its metadata.json
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Field {
+    // id of the element in the template the value goes into
+    pub selector: String,
+    pub label: String,
+    #[serde(rename = "type", default)]
+    pub kind: FieldType,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub default: Option<String>,
+    // prepended to the value in the link, like https://github.com/ for a github username
+    #[serde(default)]
+    pub link_prefix: Option<String>,
+    // what every item of a group has, matched by the data-field of the elements in its <template>
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<Field>,
+    // how many items a group or list can have
+    #[serde(default)]
+    pub max_items: Option<usize>,
+}
+
+impl Field {
+    // what a new site starts out with for this field
+    pub fn default_data(&self) -> Option<Data> {
+        self.default.as_ref().map(|value| Data {
+            selector: Some(self.selector.clone()),
+            value: Some(value.clone()),
+            link: self.link_prefix.clone(),
+            items: Vec::new(),
+        })
+    }
+
+    // checks a single item. the items of groups & lists are checked by check_items
+    pub fn check(&self, item: &Data) -> Result<(), String> {
+        if matches!(self.kind, FieldType::Group | FieldType::List) {
+            if self.required && item.items.is_empty() {
+                return Err(format!("{} needs at least one item", self.label));
+            }
+            return Ok(());
+        }
+
+        if let (Some(link), Some(prefix)) = (item.link.as_deref(), self.link_prefix.as_deref()) {
+            if !link.is_empty() && link != prefix {
+                return Err(format!("The link of {} has to be {}", self.label, prefix));
+            }
+        }
+
+        let value = match item.value.as_deref().map(str::trim) {
+            Some(value) if !value.is_empty() => value,
+            _ if self.required => return Err(format!("{} is required", self.label)),
+            _ => return Ok(()),
+        };
+
+        let valid = match self.kind {
+            FieldType::Text => true,
+            FieldType::Url => is_url(value),
+            FieldType::Email => is_email(value),
+            FieldType::Phone => is_phone(value),
+            FieldType::Date => is_date(value),
+            // either a link to an image or one of the site's own assets
+            FieldType::Image => is_url(value) || (!value.contains(':') && !value.contains(char::is_whitespace)),
+            FieldType::Group | FieldType::List => true,
+        };
+        if !valid {
+            return Err(format!("{} isn't a valid {}", self.label, self.kind.as_str()));
+        }
+        Ok(())
+    }
+}
+
+impl FieldType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Url => "url",
+            FieldType::Email => "email",
+            FieldType::Phone => "phone number",
+            FieldType::Date => "date",
+            FieldType::Image => "image",
+            FieldType::Group => "group",
+            FieldType::List => "list",
+        }
+    }
+}
+
+// checks the fields a template declares. selectors end up in css selectors, so they're kept to plain ids
+pub fn validate_fields(fields: &[Field]) -> Result<(), AppError> {
+    let mut seen = HashSet::new();
+    for field in fields {
+        let valid = !field.selector.is_empty()
+            && field.selector.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(AppError::Validation(format!("{} isn't a valid selector for a field", field.selector)));
+        }
+        if !seen.insert(field.selector.as_str()) {
+            return Err(AppError::Validation(format!("The field {} is declared more than once", field.selector)));
+        }
+
+        match (field.kind, field.fields.is_empty()) {
+            (FieldType::Group, true) => {
+                return Err(AppError::Validation(format!("The group {} needs fields for its items", field.selector)));
+            },
+            (FieldType::Group, false) => validate_fields(&field.fields)?,
+            (_, false) => {
+                return Err(AppError::Validation(format!("Only groups can have fields, {} isn't one", field.selector)));
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+// checks the data of a site against the fields of its template, with an error for every field that's off.
+// errors inside a group are reported under <group>.<item>.<field>.
+// templates that don't declare any fields take anything, like they always have.
+pub fn validate_data(fields: &[Field], data: &[Data]) -> Result<(), AppError> {
+    if fields.is_empty() {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    check_data(fields, data, "", &mut errors);
+    if !errors.is_empty() {
+        return Err(AppError::InvalidFields(errors));
+    }
+    Ok(())
+}
+
+fn check_data(fields: &[Field], data: &[Data], prefix: &str, errors: &mut Vec<FieldError>) {
+    let mut error = |selector: String, message: String| errors.push(FieldError { selector, message });
+    let mut seen = HashSet::new();
+    let mut nested = Vec::new();
+
+    for item in data {
+        let selector = item.selector.clone().unwrap_or_default();
+        let path = format!("{}{}", prefix, selector);
+        if !seen.insert(selector.clone()) {
+            error(path, String::from("Given more than once"));
+            continue;
+        }
+
+        match fields.iter().find(|field| field.selector == selector) {
+            Some(field) => {
+                if let Err(message) = field.check(item) {
+                    error(path.clone(), message);
+                }
+                if let Some(max) = field.max_items.filter(|max| item.items.len() > *max) {
+                    error(path.clone(), format!("{} can't have more than {} items", field.label, max));
+                }
+                if !item.items.is_empty() {
+                    nested.push((field, item, path));
+                }
+            },
+            None => error(path, String::from("This template has no such field")),
+        }
+    }
+
+    for field in fields.iter().filter(|field| field.required && !seen.contains(&field.selector)) {
+        error(format!("{}{}", prefix, field.selector), format!("{} is required", field.label));
+    }
+
+    for (field, item, path) in nested {
+        if !matches!(field.kind, FieldType::Group | FieldType::List) {
+            errors.push(FieldError {
+                selector: path,
+                message: format!("{} isn't a group or a list, so it can't have items", field.label),
+            });
+            continue;
+        }
+
+        for (i, entry) in item.items.iter().enumerate() {
+            if field.kind == FieldType::Group {
+                check_data(&field.fields, entry, &format!("{}.{}.", path, i), errors);
+                continue;
+            }
+
+            let plain = matches!(
+                entry.as_slice(),
+                [value] if value.selector.as_deref().unwrap_or_default().is_empty() && value.items.is_empty()
+            );
+            if !plain {
+                errors.push(FieldError {
+                    selector: format!("{}.{}", path, i),
+                    message: String::from("Every item of a list is a single value without a selector"),
+                });
+            }
+        }
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    (value.starts_with("https://") || value.starts_with("http://"))
+        && value.len() > "https://".len()
+        && !value.contains(char::is_whitespace)
+}
+
+fn is_email(value: &str) -> bool {
+    match value.split_once('@') {
+        Some((user, domain)) => {
+            !user.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !value.contains(char::is_whitespace)
+                && !domain.contains('@')
+        },
+        None => false,
+    }
+}
+
+fn is_phone(value: &str) -> bool {
+    let digits = value.chars().filter(char::is_ascii_digit).count();
+    value.chars().all(|c| c.is_ascii_digit() || " +-().".contains(c)) && (5..=15).contains(&digits)
+}
+
+fn is_date(value: &str) -> bool {
+    ["%Y-%m-%d", "%d %B, %Y", "%d %B %Y", "%B %d, %Y", "%d/%m/%Y"]
+        .iter()
+        .any(|format| NaiveDate::parse_from_str(value, format).is_ok())
+}
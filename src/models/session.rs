@@ -0,0 +1,130 @@
+use chrono::{Duration, Utc};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::token::hash_token;
+use crate::error::AppError;
+use crate::AppState;
+
+// a login on one device. access tokens carry the id of their session, so revoking it
+// logs that device out once the access token expires, and refreshing is no longer possible.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    // only the hash of the current refresh token is stored
+    pub refresh_hash: String,
+    pub created: String,
+    pub expires: i64,
+    pub revoked: bool,
+}
+
+impl Session {
+    // starts a new session & returns it along with its first refresh token
+    pub async fn create(
+        user_id: ObjectId,
+        app_state: &Arc<AppState>,
+    ) -> Result<(Session, String), AppError> {
+        let id = ObjectId::new();
+        let refresh_token = new_refresh_token(&id);
+        let session = Session {
+            id,
+            user: user_id,
+            refresh_hash: hash_token(&refresh_token),
+            created: Utc::now().to_rfc3339(),
+            expires: (Utc::now() + Duration::seconds(app_state.refresh_token_ttl)).timestamp(),
+            revoked: false,
+        };
+
+        let sessions: Collection<Session> = app_state.db.collection("sessions");
+        sessions.insert_one(&session, None).await?;
+        Ok((session, refresh_token))
+    }
+
+    // swaps a refresh token for a new one. refresh tokens are single use, so an old one showing up
+    // again means it has leaked & the whole session is revoked.
+    pub async fn rotate(
+        refresh_token: &str,
+        app_state: &Arc<AppState>,
+    ) -> Result<(Session, String), AppError> {
+        let id = refresh_token
+            .split_once('.')
+            .and_then(|(id, _)| ObjectId::parse_str(id).ok())
+            .ok_or_else(|| AppError::Unauthorized("invalid refresh token".into()))?;
+
+        // the token is checked & replaced in one go, so two refreshes with the same token can't both get through
+        let hash = hash_token(refresh_token);
+        let now = Utc::now().timestamp();
+        let new_token = new_refresh_token(&id);
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        let sessions: Collection<Session> = app_state.db.collection("sessions");
+        let rotated = sessions
+            .find_one_and_update(
+                doc! { "_id": id, "refresh_hash": &hash, "revoked": false, "expires": { "$gt": now } },
+                doc! { "$set": {
+                    "refresh_hash": hash_token(&new_token),
+                    "expires": now + app_state.refresh_token_ttl,
+                } },
+                options,
+            )
+            .await?;
+        if let Some(session) = rotated {
+            return Ok((session, new_token));
+        }
+
+        let session = sessions
+            .find_one(doc! { "_id": id }, None)
+            .await?
+            .ok_or_else(|| AppError::Unauthorized("invalid refresh token".into()))?;
+
+        // the current token of a session that ran out, or was logged out
+        if session.refresh_hash == hash {
+            return Err(AppError::Unauthorized("this session has expired, login again".into()));
+        }
+
+        Session::revoke(id, app_state).await?;
+        Err(AppError::Unauthorized("this refresh token has already been used, login again".into()))
+    }
+
+    pub async fn is_active(id: ObjectId, app_state: &Arc<AppState>) -> Result<bool, AppError> {
+        let sessions: Collection<Session> = app_state.db.collection("sessions");
+        Ok(sessions
+            .find_one(doc! { "_id": id, "revoked": false, "expires": { "$gt": Utc::now().timestamp() } }, None)
+            .await?
+            .is_some())
+    }
+
+    pub async fn revoke(id: ObjectId, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let sessions: Collection<Session> = app_state.db.collection("sessions");
+        sessions.update_one(doc! { "_id": id }, doc! { "$set": { "revoked": true } }, None).await?;
+        Ok(())
+    }
+
+    // logs the user out of every device except the one the request came from
+    pub async fn revoke_others(user_id: ObjectId, keep: ObjectId, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let sessions: Collection<Session> = app_state.db.collection("sessions");
+        sessions.update_many(
+            doc! { "user": user_id, "_id": { "$ne": keep } },
+            doc! { "$set": { "revoked": true } },
+            None
+        ).await?;
+        Ok(())
+    }
+
+    // logs the user out of all devices
+    pub async fn revoke_all(user_id: ObjectId, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let sessions: Collection<Session> = app_state.db.collection("sessions");
+        sessions.update_many(doc! { "user": user_id }, doc! { "$set": { "revoked": true } }, None).await?;
+        Ok(())
+    }
+}
+
+// the session id is part of the token so that it can be looked up without scanning every hash
+fn new_refresh_token(id: &ObjectId) -> String {
+    format!("{}.{}{}", id.to_hex(), Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
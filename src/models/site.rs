@@ -1,37 +1,52 @@
 use actix_files::NamedFile;
 use actix_web::web;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
 use mongodb::bson::{doc, oid::ObjectId, Bson, to_bson};
 use mongodb::Collection;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use serde_json::to_string;
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tempfile::TempDir;
 use utoipa::ToSchema;
+use uuid::Uuid;
 
+use crate::models::revision::Revision;
+use crate::models::moderation::Moderation;
 use crate::models::template::Template;
-use crate::services::preview::{build_html_string, generate_preview, Preview};
+use crate::models::schema::{validate_data, Field};
+use crate::models::template_version::{TemplateRef, TemplateVersion};
+use crate::models::user::User;
+use crate::services::builder::build;
+use crate::services::paths;
+use crate::services::preview::PdfOptions;
+use crate::error::AppError;
 use crate::AppState;
 
-#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 pub struct Data {
-    selector: Option<String>,
-    value: Option<String>,
-    link: Option<String>,
+    pub selector: Option<String>,
+    pub value: Option<String>,
+    pub link: Option<String>,
+    // for repeated groups & lists, the data of every item in order. the value & link aren't used then.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<Vec<Data>>,
 }
 
 impl Data {
     pub fn to_bson(&self) -> Bson {
-        doc! {
+        let mut document = doc! {
             "selector": self.selector.clone(),
             "value": self.value.clone(),
             "link": self.link.clone()
-        }.into()
+        };
+        if !self.items.is_empty() {
+            let items = self.items.iter().map(|item| item.iter().map(Data::to_bson).collect::<Vec<Bson>>());
+            document.insert("items", items.map(Bson::Array).collect::<Vec<Bson>>());
+        }
+        document.into()
     }
 }
 
@@ -50,6 +65,84 @@ impl MetaData {
             time: Utc::now().to_rfc3339(),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// the html that's live at zitefy.com/<username> when the site is active.
+// edits only make it here when the site is published again.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Published {
+    pub html: String,
+    pub time: String,
+    // missing for sites published before profile changes were carried over to the live page
+    #[serde(default)]
+    pub source: Option<PublishedSource>,
+}
+
+// what the published html was built from, so that it can be built again with a new profile
+// without whatever is in the draft going live along with it
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PublishedSource {
+    pub html: String,
+    pub css: String,
+    pub js: String,
+    pub data: Vec<Data>,
+    // the profile as it was synced to the data, like Site::profile
+    pub profile: Vec<Data>,
+}
+
+impl PublishedSource {
+    fn build(&self) -> String {
+        build(&self.html, &self.css, &self.js, &self.data)
+    }
+}
+
+// a file uploaded to a site, as it's listed
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Asset {
+    pub name: String,
+    pub size: u64,
+    pub content_type: String,
+    pub uploaded: String,
+}
+
+// the only kinds of files that can be uploaded as assets, by extension. nothing that a browser would run,
+// since assets are served from the api's domain.
+const ASSET_TYPES: [(&str, &str); 20] = [
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("pdf", "application/pdf"),
+    ("txt", "text/plain"),
+    ("json", "application/json"),
+];
+
+fn asset_type(name: &str) -> Option<&'static str> {
+    let extension = Path::new(name).extension()?.to_str()?.to_lowercase();
+    ASSET_TYPES.iter().find(|(ext, _)| *ext == extension).map(|(_, content_type)| *content_type)
+}
+
+// asset names are plain file names of an allowed type
+fn check_asset_name(name: &str) -> Result<&'static str, AppError> {
+    paths::file_name(name)?;
+    asset_type(name).ok_or_else(|| AppError::Validation(format!("{} isn't a type of file that can be uploaded", name)))
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
@@ -60,31 +153,47 @@ pub struct Site {
     pub data: Vec<Data>,
     pub metadata: MetaData,
     pub user: ObjectId,
+    #[serde(default)]
+    pub published: Option<Published>,
+    // unguessable token for previewing the draft at zitefy.com/draft/<token>
+    #[serde(default)]
+    pub draft_token: Option<String>,
+    // set by an admin. a taken down site can't be published & zitefy.com serves a notice in its place
+    #[serde(default)]
+    pub taken_down: Option<Moderation>,
+    // missing for sites created before templates were versioned
+    #[serde(default)]
+    pub template: Option<TemplateRef>,
+    // the owner's profile as it was last synced to the site, to tell it apart from edits made on the site
+    #[serde(default)]
+    pub profile: Vec<Data>,
 }
 
 // most of the names & code are self-explanatory. nothing much to document per se
 impl Site {
+    #[allow(clippy::new_ret_no_self)]
     pub async fn new(
         template_id: ObjectId,
         user_id: ObjectId,
         app_state: &Arc<AppState>,
-    ) -> Result<ObjectId, Box<dyn std::error::Error>> {
+    ) -> Result<ObjectId, AppError> {
         let site_id = ObjectId::new();
         let _ = Template::build_site(site_id, user_id, template_id, app_state).await?;
         let site = Site::from(site_id, app_state).await?;
-        site.update_preview().await?;
+        Revision::create(&site, user_id, app_state).await?;
+        site.update_preview(app_state).await?;
         Ok(site_id)
     }
 
     pub async fn from(
         id: ObjectId,
         app_state: &Arc<AppState>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, AppError> {
         let sites: Collection<Site> = app_state.db.collection("sites");
-        Ok(sites
+        sites
             .find_one(doc! { "_id": id }, None)
             .await?
-            .ok_or("site not found")?)
+            .ok_or_else(|| AppError::NotFound("site not found".into()))
     }
 
     pub async fn rename(&mut self, new_name: String, app_state: &Arc<AppState>) -> String {
@@ -123,48 +232,125 @@ impl Site {
         }
     }
 
-    pub fn get_source(self) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    pub fn get_source(self) -> Result<serde_json::Value, AppError> {
         let site_dir = Path::new(&self.path);
 
-        let html = match read_to_string(&site_dir.join("index.html")) {
-            Ok(html) => html,
-            Err(_) => String::from(""),
-        };
+        let html = read_to_string(&site_dir.join("index.html")).unwrap_or_default();
+        let js = read_dir_to_string(&site_dir.join("js")).unwrap_or_default();
+        let css = read_dir_to_string(&site_dir.join("styles")).unwrap_or_default();
+        let resources = read_dir_to_names(&site_dir.join("resources")).unwrap_or_default();
 
-        let js = match read_dir_to_string(&site_dir.join("js")) {
-            Ok(js) => js,
-            Err(_) => String::from(""),
-        };
+        Ok(json!({ "html": html, "js": js, "css": css, "assets": resources }))
+    }
 
-        let css = match read_dir_to_string(&site_dir.join("styles")) {
-            Ok(css) => css,
-            Err(_) => String::from(""),
-        };
+    fn resources_dir(&self) -> PathBuf {
+        Path::new(&self.path).join("resources")
+    }
 
-        let resources = match read_dir_to_names(&site_dir.join("resources")) {
-            Ok(resources) => resources,
-            Err(_) => vec![],
-        };
+    fn resource_path(&self, name: &str) -> Result<PathBuf, AppError> {
+        paths::join(&self.resources_dir(), paths::file_name(name)?)
+    }
 
-        Ok(json!({ "html": html, "js": js, "css": css, "assets": resources }))
+    // every asset of the site, oldest first
+    pub fn assets(&self) -> Result<Vec<Asset>, AppError> {
+        let resources_dir = self.resources_dir();
+        if !resources_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut assets = Vec::new();
+        for entry in fs::read_dir(resources_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let uploaded: DateTime<Utc> = metadata.modified()?.into();
+            assets.push(Asset {
+                content_type: asset_type(&name).unwrap_or("application/octet-stream").to_string(),
+                name,
+                size: metadata.len(),
+                uploaded: uploaded.to_rfc3339(),
+            });
+        }
+        assets.sort_by(|a, b| a.uploaded.cmp(&b.uploaded));
+        Ok(assets)
     }
 
+    // uploads an asset, or replaces the one with the same name. the site & the owner have to stay within their quotas.
     pub async fn save_resource(
         self,
         file_name: &str,
         file_content: &[u8],
-    ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-        let resources_dir = Path::new(&self.path).join("resources");
+        app_state: &Arc<AppState>,
+    ) -> Result<Vec<Asset>, AppError> {
+        check_asset_name(file_name)?;
+
+        let assets = self.assets()?;
+        let existing = assets.iter().find(|asset| asset.name == file_name);
+        if existing.is_none() && assets.len() >= app_state.asset_limit {
+            return Err(AppError::Validation(format!("A site can't have more than {} assets", app_state.asset_limit)));
+        }
+
+        // replacing an asset frees up whatever it took before
+        let replaced = existing.map_or(0, |asset| asset.size);
+        let size = file_content.len() as u64;
+        let site_usage: u64 = assets.iter().map(|asset| asset.size).sum();
+        if site_usage - replaced + size > app_state.site_quota {
+            return Err(AppError::Validation(format!(
+                "This would take the site over its storage quota of {} MB",
+                app_state.site_quota / (1024 * 1024)
+            )));
+        }
+
+        let mut user_usage = 0;
+        for site in Site::get_by_user(self.user, app_state).await? {
+            user_usage += site.assets()?.iter().map(|asset| asset.size).sum::<u64>();
+        }
+        if user_usage - replaced + size > app_state.user_quota {
+            return Err(AppError::Validation(format!(
+                "This would take the account over its storage quota of {} MB",
+                app_state.user_quota / (1024 * 1024)
+            )));
+        }
 
+        let resources_dir = self.resources_dir();
         if !resources_dir.exists() {
             fs::create_dir_all(&resources_dir)?;
         }
 
-        let file_path = resources_dir.join(file_name);
+        let file_path = self.resource_path(file_name)?;
         let mut file = File::create(file_path)?;
         file.write_all(file_content)?;
 
-        Ok(json!({ "assets": read_dir_to_names(&resources_dir).unwrap() }))
+        self.assets()
+    }
+
+    pub fn delete_resource(&self, file_name: &str) -> Result<Vec<Asset>, AppError> {
+        let path = match self.resource_path(file_name) {
+            Ok(path) if path.is_file() => path,
+            _ => return Err(AppError::NotFound("Requested resource doesn't exist".into())),
+        };
+        fs::remove_file(path)?;
+        self.assets()
+    }
+
+    // the html isn't touched, so references to the old name have to be updated by the user
+    pub fn rename_resource(&self, from: &str, to: &str) -> Result<Vec<Asset>, AppError> {
+        let source = match self.resource_path(from) {
+            Ok(path) if path.is_file() => path,
+            _ => return Err(AppError::NotFound("Requested resource doesn't exist".into())),
+        };
+
+        check_asset_name(to)?;
+        let destination = self.resource_path(to)?;
+        if destination.exists() {
+            return Err(AppError::Conflict(format!("There's already an asset named {}", to)));
+        }
+        fs::rename(source, destination)?;
+        self.assets()
     }
 
     pub async fn save_source(
@@ -172,7 +358,9 @@ impl Site {
         html_content: &[u8],
         css_content: &[u8],
         js_content: &[u8],
-    ) -> io::Result<()> {
+        author: ObjectId,
+        app_state: &Arc<AppState>,
+    ) -> Result<(), AppError> {
         let base_dir = Path::new(&self.path);
         let resources_dir = base_dir.join("resources");
 
@@ -180,19 +368,20 @@ impl Site {
             fs::create_dir_all(&resources_dir)?;
         }
 
-        let html_path = base_dir.join("index.html");
+        let html_path = paths::join(base_dir, "index.html")?;
         let mut html_file = File::create(html_path)?;
         html_file.write_all(html_content)?;
 
-        let css_path = base_dir.join("styles").join("styles.css");
+        let css_path = paths::join(base_dir, "styles/styles.css")?;
         let mut css_file = File::create(css_path)?;
         css_file.write_all(css_content)?;
 
-        let js_path = base_dir.join("js").join("script.js");
+        let js_path = paths::join(base_dir, "js/script.js")?;
         let mut js_file = File::create(js_path)?;
         js_file.write_all(js_content)?;
 
-        self.update_preview().await.unwrap();
+        Revision::create(&self, author, app_state).await?;
+        self.update_preview(app_state).await?;
 
         Ok(())
     }
@@ -200,92 +389,309 @@ impl Site {
     pub async fn save(
         site_id: ObjectId,
         new_data: Vec<Data>,
+        author: ObjectId,
         app_state: &Arc<AppState>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), AppError> {
+        let site = Site::from(site_id, app_state).await?;
+        validate_data(&site.fields(app_state).await?, &new_data)?;
+
         let sites: Collection<Site> = app_state.db.collection("sites");
         sites.update_one(
             doc! { "_id": site_id }, 
             doc! { "$set": { "data": to_bson(&new_data)?.as_array().cloned().unwrap() } },
             None
         ).await?;
+
+        let site = Site::from(site_id, app_state).await?;
+        Revision::create(&site, author, app_state).await?;
         Ok(())
     }
 
+    // the fields declared by the version of the template the site is at.
+    // sites from before templates were versioned, or whose template is gone, have none.
+    pub async fn fields(&self, app_state: &Arc<AppState>) -> Result<Vec<Field>, AppError> {
+        let template = match &self.template {
+            Some(template) => template,
+            None => return Ok(Vec::new()),
+        };
+        match TemplateVersion::from(template.id, template.version, app_state).await {
+            Ok(version) => Ok(version.fields),
+            Err(AppError::NotFound(_)) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // fills in the data from the owner's current profile, leaving alone whatever was changed on the site
+    // since the last sync. returns the selectors that were updated.
+    pub async fn sync_profile(&mut self, author: ObjectId, app_state: &Arc<AppState>) -> Result<Vec<String>, AppError> {
+        let site_id = self.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+        let users: Collection<User> = app_state.db.collection("users");
+        let user = users
+            .find_one(doc! { "_id": self.user }, None)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+
+        let profile = fit_profile(user.profile_data(&app_state.api_url), &self.fields(app_state).await?);
+        let updated = merge_profile(&mut self.data, &profile, &self.profile);
+        self.profile = profile;
+
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(
+            doc! { "_id": site_id },
+            doc! { "$set": { "data": to_bson(&self.data)?, "profile": to_bson(&self.profile)? } },
+            None
+        ).await?;
+
+        if !updated.is_empty() {
+            Revision::create(self, author, app_state).await?;
+        }
+        Ok(updated)
+    }
+
     pub async fn retrieve_resource(
         self,
         filename: String,
-    ) -> Result<NamedFile, Box<dyn std::error::Error>> {
-        let path = Path::new(&std::env::var("HOME")?)
-            .join(".zitefy")
-            .join("sites")
-            .join(self.id.unwrap().to_hex())
-            .join("resources")
-            .join(filename);
-        Ok(NamedFile::open(path)?)
+    ) -> Result<NamedFile, AppError> {
+        Ok(NamedFile::open(self.resource_path(&filename)?)?)
     }
 
-    pub async fn get_html(self) -> Result<String, Box<dyn std::error::Error>> {
+    pub async fn get_html(self) -> Result<String, AppError> {
         let base = Path::new(&self.path);
-        let temp_dir = TempDir::new()?;
-        let temp_path = temp_dir.path().join("input.json");
-        File::create(temp_path.clone())?.write_all(to_string(&self.data)?.as_bytes())?;
-
-        let temp_dir_path = temp_dir.into_path();
-        tokio::spawn(async move {
-            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-            if let Err(e) = tokio::fs::remove_dir_all(temp_dir_path).await {
-                eprintln!("Error cleaning up temporary directory: {}", e);
-            }
-        });
-        build_html_string(
-            base.join("index.html"), 
-            base.join("styles").join("styles.css"), 
-            base.join("js").join("script.js"), 
-            Some(temp_path)
-        )
+        Ok(build(
+            &read_to_string(&base.join("index.html"))?,
+            &read_to_string(&base.join("styles").join("styles.css"))?,
+            &read_to_string(&base.join("js").join("script.js"))?,
+            &self.data,
+        ))
     }
 
-    pub async fn update_preview(self) -> Result<(), Box<dyn std::error::Error>> {
+    // prints the draft, as it's built in the editor, to a pdf
+    pub async fn export_pdf(self, options: PdfOptions, app_state: &Arc<AppState>) -> Result<File, AppError> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("site.pdf");
+        let html = self.get_html().await?;
+        app_state.previews.pdf(html, path.clone(), options).await?;
+
+        // the file can still be read once the directory is gone
+        Ok(File::open(path)?)
+    }
+
+    // renders the current draft & makes it the live version. if this is the owner's active site,
+    // the page served at their username is updated as well.
+    pub async fn publish(&mut self, app_state: &Arc<AppState>) -> Result<Published, AppError> {
+        let site_id = self.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+        if self.taken_down.is_some() {
+            return Err(AppError::Forbidden("This site has been taken down by an admin".into()));
+        }
+
+        let base = Path::new(&self.path);
+        let source = PublishedSource {
+            html: read_to_string(&base.join("index.html"))?,
+            css: read_to_string(&base.join("styles").join("styles.css"))?,
+            js: read_to_string(&base.join("js").join("script.js"))?,
+            data: self.data.clone(),
+            profile: self.profile.clone(),
+        };
+        let published = Published {
+            html: source.build(),
+            time: Utc::now().to_rfc3339(),
+            source: Some(source),
+        };
+
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(
+            doc! { "_id": site_id },
+            doc! { "$set": { "published": to_bson(&published)? } },
+            None
+        ).await?;
+
+        let users: Collection<User> = app_state.db.collection("users");
+        users.update_one(
+            doc! { "_id": self.user, "active": site_id },
+            doc! { "$set": { "quick_response": &published.html } },
+            None
+        ).await?;
+
+        self.published = Some(published.clone());
+        Ok(published)
+    }
+
+    // carries a new profile over to the published site, the same way sync_profile does for the draft.
+    // the draft stays as it is, so nothing goes live that the user didn't publish.
+    pub async fn refresh_published(&mut self, user: &User, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let site_id = self.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+        let mut published = match self.published.clone() {
+            Some(published) if self.taken_down.is_none() => published,
+            _ => return Ok(()),
+        };
+        let source = match published.source.as_mut() {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+
+        let profile = fit_profile(user.profile_data(&app_state.api_url), &self.fields(app_state).await?);
+        let updated = merge_profile(&mut source.data, &profile, &source.profile);
+        source.profile = profile;
+        if updated.is_empty() {
+            return Ok(());
+        }
+        published.html = source.build();
+
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(
+            doc! { "_id": site_id },
+            doc! { "$set": { "published": to_bson(&published)? } },
+            None
+        ).await?;
+
+        let users: Collection<User> = app_state.db.collection("users");
+        users.update_one(
+            doc! { "_id": self.user, "active": site_id },
+            doc! { "$set": { "quick_response": &published.html } },
+            None
+        ).await?;
+
+        self.published = Some(published);
+        Ok(())
+    }
+
+    // takes the site off zitefy.com. the published html is kept so that the site can go back up as it was.
+    pub async fn take_down(&mut self, reason: String, by: ObjectId, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let site_id = self.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+        let moderation = Moderation { reason, time: Utc::now().to_rfc3339(), by };
+
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(
+            doc! { "_id": site_id },
+            doc! { "$set": { "taken_down": to_bson(&moderation)? } },
+            None
+        ).await?;
+
+        let users: Collection<User> = app_state.db.collection("users");
+        users.update_one(
+            doc! { "_id": self.user, "active": site_id },
+            doc! { "$set": { "quick_response": null } },
+            None
+        ).await?;
+
+        self.taken_down = Some(moderation);
+        Ok(())
+    }
+
+    pub async fn lift_take_down(&mut self, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let site_id = self.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(doc! { "_id": site_id }, doc! { "$set": { "taken_down": null } }, None).await?;
+        self.taken_down = None;
+
+        if let Some(published) = &self.published {
+            let users: Collection<User> = app_state.db.collection("users");
+            users.update_one(
+                doc! { "_id": self.user, "active": site_id },
+                doc! { "$set": { "quick_response": &published.html } },
+                None
+            ).await?;
+        }
+        Ok(())
+    }
+
+    // sites created before drafts were a thing don't have a token yet, so one is generated on demand
+    pub async fn draft_token(&mut self, app_state: &Arc<AppState>) -> Result<String, AppError> {
+        if let Some(token) = &self.draft_token {
+            return Ok(token.clone());
+        }
+
+        let token = Uuid::new_v4().simple().to_string();
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(
+            doc! { "_id": self.id },
+            doc! { "$set": { "draft_token": &token } },
+            None
+        ).await?;
+
+        self.draft_token = Some(token.clone());
+        Ok(token)
+    }
+
+    pub async fn status(mut self, app_state: &Arc<AppState>) -> Result<serde_json::Value, AppError> {
+        let users: Collection<User> = app_state.db.collection("users");
+        let live = users
+            .find_one(doc! { "_id": self.user, "active": self.id }, None)
+            .await?
+            .is_some();
+
+        let token = self.draft_token(app_state).await?;
+        let draft = self.clone().get_html().await?;
+
+        Ok(json!({
+            "published": self.published.is_some(),
+            "published_at": self.published.as_ref().map(|published| published.time.clone()),
+            "live": live,
+            "unpublished_changes": self.published.as_ref().is_none_or(|published| published.html != draft),
+            "draft_url": format!("/draft/{}", token),
+        }))
+    }
+
+    pub async fn update_preview(self, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        self.render_preview(false, app_state).await
+    }
+
+    // renders the previews again even if the html hasn't changed, for admins to fix broken ones
+    pub async fn regenerate_preview(self, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        self.render_preview(true, app_state).await
+    }
+
+    async fn render_preview(self, fresh: bool, app_state: &Arc<AppState>) -> Result<(), AppError> {
         let base_path = Path::new(&self.path).join("previews");
         let html = self.get_html().await?;
         if !base_path.exists() {
-            fs::create_dir_all(base_path.clone()).unwrap();
+            fs::create_dir_all(base_path.clone())?;
         }
 
-        generate_preview(&html, Some(Preview {
-            mobile: base_path.join("mobile.png"),
-            desktop: base_path.join("desktop.png")
-        })).await?;
+        app_state.previews.render(html, Some(&base_path), fresh).await?;
         Ok(())   
     }
 
-    pub async fn get_preview(self, is_mobile: bool) -> Result<NamedFile, Box<dyn std::error::Error>> {
-        let base_path = Path::new(&self.path).join("previews");
-        let path = match is_mobile {
-            true => base_path.join("mobile.png"),
-            false => base_path.join("desktop.png")
-        };
+    // viewports added since the site was last rendered are rendered on the first request for them
+    pub async fn get_preview(self, viewport: &str, app_state: &Arc<AppState>) -> Result<NamedFile, AppError> {
+        let settings = app_state.previews.settings();
+        let path = Path::new(&self.path).join("previews").join(settings.file_name(settings.viewport(viewport)?));
+        if !path.is_file() {
+            self.update_preview(app_state).await?;
+        }
         Ok(NamedFile::open(&path)?)
     }
 
-    pub async fn is_owner(
-        site_id: ObjectId,
-        user_id: ObjectId,
-        app_state: &web::Data<Arc<AppState>>,
-    ) -> Result<bool, actix_web::Error> {
+    // removes the site document & everything on disk, and takes it down if it was the owner's active site
+    pub async fn delete(self, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let site_id = self.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+
         let sites: Collection<Site> = app_state.db.collection("sites");
-        let filter = doc! { "_id": site_id, "user": user_id };
-        match sites.find_one(filter, None).await {
-            Ok(Some(_)) => Ok(true),
-            Ok(None) => Ok(false),
-            Err(_) => Ok(false),
+        sites.delete_one(doc! { "_id": site_id }, None).await?;
+
+        let revisions: Collection<Revision> = app_state.db.collection("revisions");
+        revisions.delete_many(doc! { "site": site_id }, None).await?;
+
+        let users: Collection<User> = app_state.db.collection("users");
+        users.update_one(
+            doc! { "_id": self.user, "active": site_id },
+            doc! { "$set": { "active": Bson::Null, "quick_response": Bson::Null } },
+            None
+        ).await?;
+
+        let site_dir = Path::new(&self.path);
+        if site_dir.exists() {
+            fs::remove_dir_all(site_dir)?;
         }
+
+        Ok(())
     }
 
     pub async fn get_by_user(
         id: ObjectId,
-        app_state: &web::Data<Arc<AppState>>,
-    ) -> Result<Vec<Site>, Box<dyn std::error::Error>> {
+        app_state: &Arc<AppState>,
+    ) -> Result<Vec<Site>, AppError> {
         let collection: Collection<Site> = app_state.db.collection("sites");
 
         let cursor = collection.find(doc! { "user": id }, None).await?;
@@ -295,41 +701,53 @@ impl Site {
     }
 }
 
+// queues a preview of code from the editor, returning the id of the job
 pub async fn preview_code(
     html: &str,
     css: &str,
     js: &str,
     data: &[Data],
+    owner: ObjectId,
     app_state: &web::Data<Arc<AppState>>,
-) -> Result<(String, String), Box<dyn std::error::Error>> {
-    let temp_dir = TempDir::new()?;
-
-    let html_path = temp_dir.path().join("input.html");
-    let css_path = temp_dir.path().join("input.css");
-    let js_path = temp_dir.path().join("input.js");
-    let data_path = temp_dir.path().join("input.json");
-
-    // Write input to temporary files
-    File::create(&html_path)?.write_all(html.as_bytes())?;
-    File::create(&css_path)?.write_all(css.as_bytes())?;
-    File::create(&js_path)?.write_all(js.as_bytes())?;
-    File::create(&data_path)?.write_all(to_string(data)?.as_bytes())?;
-
-    let result = build_html_string(html_path, css_path, js_path, Some(data_path))?;
-
-    // Schedule cleanup after 1 minute
-    let temp_dir_path = temp_dir.into_path();
-    tokio::spawn(async move {
-        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-        if let Err(e) = tokio::fs::remove_dir_all(temp_dir_path).await {
-            eprintln!("Error cleaning up temporary directory: {}", e);
+) -> Result<String, AppError> {
+    let result = build(html, css, js, data);
+    app_state.previews.submit(result, owner).await
+}
+
+// the profile data the template has a place for, with the links it expects.
+// templates that don't declare fields get all of it, the builder skips what isn't in the html anyway.
+pub fn fit_profile(profile: Vec<Data>, fields: &[Field]) -> Vec<Data> {
+    if fields.is_empty() {
+        return profile;
+    }
+
+    profile
+        .into_iter()
+        .filter_map(|mut item| {
+            let field = fields.iter().find(|field| item.selector.as_ref() == Some(&field.selector))?;
+            if field.link_prefix.is_some() {
+                item.link = field.link_prefix.clone();
+            }
+            field.check(&item).ok()?;
+            Some(item)
+        })
+        .collect()
+}
+
+// puts the profile into the data. an item that doesn't match what was last synced was edited on the site,
+// so it stays as it is. returns the selectors that were updated.
+pub fn merge_profile(data: &mut Vec<Data>, profile: &[Data], synced: &[Data]) -> Vec<String> {
+    let mut updated = Vec::new();
+    for item in profile {
+        let previous = synced.iter().find(|synced| synced.selector == item.selector);
+        match data.iter_mut().find(|current| current.selector == item.selector) {
+            Some(current) if *current == *item || previous != Some(&*current) => continue,
+            Some(current) => *current = item.clone(),
+            None => data.push(item.clone()),
         }
-    });
-    let paths = generate_preview(&result, None).await?;
-    Ok((
-        app_state.tempfiles.add_file(paths.mobile).await,
-        app_state.tempfiles.add_file(paths.desktop).await,
-    ))
+        updated.push(item.selector.clone().unwrap_or_default());
+    }
+    updated
 }
 
 pub fn read_to_string(path: &Path) -> io::Result<String> {
@@ -344,7 +762,7 @@ pub fn read_dir_to_string(dir: &Path) -> io::Result<String> {
     for entry in fs::read_dir(dir)? {
         let entry = entry?;
         let path = entry.path();
-        if path.is_file() {
+        if entry.file_type()?.is_file() {
             contents.push_str(&read_to_string(&path)?);
             contents.push('\n');
         }
@@ -358,10 +776,9 @@ pub fn read_dir_to_names(dir: &Path) -> io::Result<Vec<String>> {
     for entry in fs::read_dir(dir)? {
         let entry = entry?;
         let path = entry.path();
-        if path.is_file() {
+        if entry.file_type()?.is_file() {
             if let Some(filename) = path.file_name().and_then(|name| name.to_str()) {
-                let url = format!("{}", filename);
-                urls.push(url);
+                urls.push(filename.to_string());
             }
         }
     }
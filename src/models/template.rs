@@ -1,19 +1,27 @@
 use actix_files::NamedFile;
 use actix_web::web;
-use mongodb::bson::{doc, oid::ObjectId, to_bson};
+use mongodb::bson::{doc, oid::ObjectId, to_bson, to_document};
 use mongodb::Collection;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use std::fs;
+use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::io;
 use std::fs::create_dir_all;
+use uuid::Uuid;
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
 
-use crate::services::preview::{build_html_string, generate_preview, Preview};
+use crate::services::paths;
+use crate::services::preview::{build_html_string, Preview};
+use crate::error::AppError;
 use crate::AppState;
-use crate::models::site::Site;
-use crate::models::site::MetaData;
+use crate::models::site::{fit_profile, merge_profile, Data, MetaData, Site};
+use crate::models::user::User;
+use crate::models::schema::{validate_fields, Field};
+use crate::models::template_version::{TemplateRef, TemplateVersion};
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Template {
@@ -27,21 +35,152 @@ pub struct Template {
     #[serde(default)]
     pub previews: Preview,
     #[serde(default)]
-    pub dir_path: String
+    pub dir_path: String,
+    // the user who uploaded it. templates from the templates directory don't have one
+    #[serde(default)]
+    pub owner: Option<ObjectId>,
+    #[serde(default = "first_version")]
+    pub version: u32,
+    // hash of the files the preview is rendered from, so it's only rendered again when they change
+    #[serde(default)]
+    pub content_hash: String,
+    // the data the template expects, so that sites can be checked against it
+    #[serde(default)]
+    pub fields: Vec<Field>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+// uploads bigger than this, zipped or not, are turned away
+pub const MAX_BUNDLE_SIZE: usize = 20 * 1024 * 1024;
+
+// every template needs these. the builder reads the css & js from these exact paths
+const BUNDLE_FILES: [&str; 4] = ["index.html", "metadata.json", "styles/styles.css", "js/script.js"];
+
+// an uploaded template, unpacked into a staging directory until it's validated & published.
+// whatever is left of it is removed when it's dropped.
+pub struct TemplateBundle {
+    dir: PathBuf,
+    size: usize,
+}
+
+impl TemplateBundle {
+    pub fn new() -> Result<TemplateBundle, AppError> {
+        // staged next to the templates directory so publishing is just a rename
+        let dir = paths::zitefy_dir()?
+            .join("uploads")
+            .join(Uuid::new_v4().simple().to_string());
+        fs::create_dir_all(&dir)?;
+        Ok(TemplateBundle { dir, size: 0 })
+    }
+
+    // adds a single file, at the given path relative to the template root
+    pub fn add_file(&mut self, path: &str, content: &[u8]) -> Result<(), AppError> {
+        let dest = paths::join(&self.dir, path)?;
+        self.grow(content.len())?;
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(dest, content)?;
+        Ok(())
+    }
+
+    // unpacks a zipped template into the bundle
+    pub fn add_zip(&mut self, content: &[u8]) -> Result<(), AppError> {
+        let mut archive = ZipArchive::new(Cursor::new(content))
+            .map_err(|e| AppError::Validation(format!("The bundle isn't a valid zip: {}", e)))?;
+
+        for i in 0..archive.len() {
+            let file = archive
+                .by_index(i)
+                .map_err(|e| AppError::Validation(format!("The bundle isn't a valid zip: {}", e)))?;
+            if file.is_dir() || file.name().starts_with("__MACOSX/") {
+                continue;
+            }
+
+            let path = file
+                .enclosed_name()
+                .ok_or_else(|| AppError::Validation(format!("{} isn't a valid path inside a template", file.name())))?;
+
+            // the size in the header can't be trusted, so reading stops at the limit either way
+            let mut content = Vec::new();
+            file.take((MAX_BUNDLE_SIZE - self.size + 1) as u64).read_to_end(&mut content)?;
+            self.add_file(&path.to_string_lossy(), &content)?;
+        }
+        Ok(())
+    }
+
+    fn grow(&mut self, by: usize) -> Result<(), AppError> {
+        self.size += by;
+        if self.size > MAX_BUNDLE_SIZE {
+            return Err(AppError::Validation(format!(
+                "Templates can't be bigger than {} MB",
+                MAX_BUNDLE_SIZE / (1024 * 1024)
+            )));
+        }
+        Ok(())
+    }
+
+    // zips usually wrap everything in a single folder, in which case that's the template
+    fn root(&self) -> Result<PathBuf, AppError> {
+        if self.dir.join("index.html").exists() {
+            return Ok(self.dir.clone());
+        }
+
+        let entries: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| !path.file_name().unwrap_or_default().to_string_lossy().starts_with('.'))
+            .collect();
+        match entries.as_slice() {
+            [only] if only.is_dir() => Ok(only.clone()),
+            _ => Ok(self.dir.clone()),
+        }
+    }
+
+    // checks that everything a template needs is there & reads its metadata
+    fn validate(&self) -> Result<(PathBuf, Template), AppError> {
+        let root = self.root()?;
+        for file in BUNDLE_FILES {
+            if !root.join(file).is_file() {
+                return Err(AppError::Validation(format!("The template is missing {}", file)));
+            }
+        }
+
+        let metadata = fs::File::open(root.join("metadata.json"))?;
+        let template: Template = serde_json::from_reader(metadata)
+            .map_err(|e| AppError::Validation(format!("metadata.json isn't valid: {}", e)))?;
+        if template.name.trim().is_empty() {
+            return Err(AppError::Validation("The template needs a name in metadata.json".into()));
+        }
+        validate_fields(&template.fields)?;
+
+        Ok((root, template))
+    }
+}
+
+impl Drop for TemplateBundle {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
 }
 
 impl Template {
     // add a template from metadata.json in the directory
-    pub async fn from_metadata(path: &Path) -> Result<Template, Box<dyn std::error::Error>> {
+    pub fn from_metadata(path: &Path) -> Result<Template, AppError> {
         let metadata_path = path.join("metadata.json");
         let metadata_file = fs::File::open(metadata_path)?;
-        let mut template: Template = serde_json::from_reader(metadata_file)?;
+        let mut template: Template = serde_json::from_reader(metadata_file)
+            .map_err(|e| AppError::Validation(format!("metadata.json isn't valid: {}", e)))?;
+        validate_fields(&template.fields)?;
         template.dir_path = path.to_string_lossy().to_string();
-        template.previews = template.build_preview().await?;
+        template.content_hash = content_hash(path)?;
         Ok(template)
     }
 
-    pub async fn build_preview(&self) -> Result<Preview, Box<dyn std::error::Error>> {
+    pub async fn build_preview(&self, app_state: &Arc<AppState>) -> Result<Preview, AppError> {
         let base_path = Path::new(&self.dir_path);
         let html = base_path.join("index.html");
         let css = base_path.join("styles/styles.css");
@@ -49,52 +188,126 @@ impl Template {
 
         let dir = base_path.join("previews");
         if !dir.exists() { fs::create_dir_all(dir.clone())?; }
-        let html = build_html_string(html, css, js, None).unwrap();
-        Ok(generate_preview(&html, Some(Preview {
-            mobile: dir.join("mobile.png"),
-            desktop: dir.join("desktop.png")
-        })).await?)
+        let html = build_html_string(html, css, js, None)?;
+        app_state.previews.render(html, Some(&dir), false).await
     }
 
     // build a site from a specified template
-    pub async fn build_site(site_id: ObjectId, user_id: ObjectId, template_id: ObjectId, app_state: &Arc<AppState>) -> Result<PathBuf, Box<dyn std::error::Error>> {
-        let home_dir = std::env::var("HOME")?;
-        let site_dir = Path::new(&home_dir).join(".zitefy").join("sites").join(site_id.to_hex());
+    pub async fn build_site(site_id: ObjectId, user_id: ObjectId, template_id: ObjectId, app_state: &Arc<AppState>) -> Result<PathBuf, AppError> {
+        let site_dir = paths::zitefy_dir()?.join("sites").join(site_id.to_hex());
 
         if !site_dir.exists() {
             create_dir_all(&site_dir)?;
         }
 
         let templates: Collection<Template> = app_state.db.collection("templates");
-        let template = templates.find_one(doc! { "_id": template_id }, None).await?.ok_or("Template not found")?;
+        let template = templates.find_one(doc! { "_id": template_id }, None).await?.ok_or_else(|| AppError::NotFound("Template not found".into()))?;
+
+        // templates from before versioning don't have their first version recorded yet
+        TemplateVersion::record(&template, app_state).await?;
 
         let template_dir = Path::new(&template.dir_path);
-        copy_dir_all(&template_dir, &site_dir, Some("previews"))?;
+        copy_dir_all(template_dir, &site_dir, Some("previews"))?;
 
         let metadata = MetaData::new(&template.name.clone(), Some(template.category.clone()));
 
+        // the site starts out with the defaults of the template, filled in from the owner's profile where it can be
+        let users: Collection<User> = app_state.db.collection("users");
+        let user = users.find_one(doc! { "_id": user_id }, None).await?.ok_or_else(|| AppError::NotFound("User not found".into()))?;
+        let profile = fit_profile(user.profile_data(&app_state.api_url), &template.fields);
+        let defaults: Vec<Data> = template.fields.iter().filter_map(Field::default_data).collect();
+        let mut data = defaults.clone();
+        merge_profile(&mut data, &profile, &defaults);
+
         let sites: Collection<Site> = app_state.db.collection("sites");
         let site = Site {
             id: Some(site_id),
             path: site_dir.to_string_lossy().into_owned(),
-            data: Vec::new(),
+            data,
             metadata,
             user: user_id,
+            published: None,
+            taken_down: None,
+            template: Some(TemplateRef { id: template_id, version: template.version }),
+            profile,
+            draft_token: Some(Uuid::new_v4().simple().to_string()),
         };
         sites.insert_one(site, None).await?;
 
         Ok(site_dir)
     }
     
-    pub async fn get_preview(id: ObjectId, is_mobile: bool, app_state: &web::Data<Arc<AppState>>) -> Result<NamedFile, Box<dyn std::error::Error>> {
-        let templates = app_state.db.collection("templates");
-        let template: Template = templates.find_one(doc! { "_id": id }, None).await?.ok_or("Template not found")?;
+    // validates an uploaded bundle, renders its preview & makes it available to everyone.
+    // with an existing template, the bundle replaces it as its next version.
+    pub async fn publish(bundle: TemplateBundle, owner: ObjectId, existing: Option<Template>, app_state: &Arc<AppState>) -> Result<Template, AppError> {
+        let (root, mut template) = bundle.validate()?;
+        template.content_hash = content_hash(&root)?;
+
+        // the directory scan tells templates apart by their names, so they have to stay unique
+        let templates: Collection<Template> = app_state.db.collection("templates");
+        if let Some(other) = templates.find_one(doc! { "name": &template.name }, None).await? {
+            if existing.as_ref().and_then(|existing| existing.id) != other.id {
+                return Err(AppError::Conflict(format!("There's already a template named {}", template.name)));
+            }
+        }
 
-        let path = match is_mobile {
-            true => Path::new(&template.previews.mobile),
-            false => Path::new(&template.previews.desktop),
+        // a new template gets a fresh id, not the all-zero default one
+        #[allow(clippy::unwrap_or_default)]
+        let id = existing.as_ref().and_then(|existing| existing.id).unwrap_or_else(ObjectId::new);
+        let dir = match &existing {
+            Some(existing) if !existing.dir_path.is_empty() => PathBuf::from(&existing.dir_path),
+            _ => templates_dir()?.join(id.to_hex()),
         };
 
+        // the preview is rendered before anything is replaced, so a broken upload leaves the old version alone
+        template.dir_path = root.to_string_lossy().into_owned();
+        template.build_preview(app_state).await?;
+
+        template.id = Some(id);
+        template.dir_path = dir.to_string_lossy().into_owned();
+        template.previews = app_state.previews.settings().files(&dir.join("previews"), "");
+        template.owner = existing.as_ref().and_then(|existing| existing.owner).or(Some(owner));
+        template.version = existing.as_ref().map_or(1, |existing| existing.version + 1);
+
+        templates.replace_one(
+            doc! { "_id": id },
+            &template,
+            mongodb::options::ReplaceOptions::builder().upsert(true).build(),
+        ).await?;
+
+        // the document goes in first, so when the directory watcher picks up the new files
+        // it finds them with the same hash & leaves them alone
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        if let Some(parent) = dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&root, &dir)?;
+
+        TemplateVersion::record(&template, app_state).await?;
+        Ok(template)
+    }
+
+    // viewports added since the template was rendered are rendered on the first request for them
+    pub async fn get_preview(id: ObjectId, viewport: &str, app_state: &web::Data<Arc<AppState>>) -> Result<NamedFile, AppError> {
+        app_state.previews.settings().viewport(viewport)?;
+        let templates: Collection<Template> = app_state.db.collection("templates");
+        let mut template = templates.find_one(doc! { "_id": id }, None).await?.ok_or_else(|| AppError::NotFound("Template not found".into()))?;
+
+        if !template.previews.get(viewport).is_some_and(|path| path.is_file()) {
+            template.previews = template.build_preview(app_state).await?;
+            templates.update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "previews": to_bson(&template.previews)? } },
+                None
+            ).await?;
+        }
+
+        let path = template
+            .previews
+            .get(viewport)
+            .ok_or_else(|| AppError::NotFound("no preview available".into()))?;
         Ok(NamedFile::open(path)?)
     }
 
@@ -119,7 +332,8 @@ fn copy_dir_all(src: &Path, dst: &Path, exclude: Option<&str>) -> io::Result<()>
             }
             let new_dst = dst.join(path.file_name().unwrap());
             copy_dir_all(&path, &new_dst, None)?;
-        } else {
+        } else if ty.is_file() {
+            // symlinks are left behind, they could point anywhere
             let new_dst = dst.join(path.file_name().unwrap());
             fs::copy(path, new_dst)?;
         }
@@ -128,31 +342,86 @@ fn copy_dir_all(src: &Path, dst: &Path, exclude: Option<&str>) -> io::Result<()>
     Ok(())
 }
 
+pub fn templates_dir() -> Result<PathBuf, std::env::VarError> {
+    Ok(paths::zitefy_dir()?.join("templates"))
+}
+
+// adds or updates every template in the templates directory. returns how many made it,
+// along with the directories that failed & why
+pub async fn sync_templates(app_state: &Arc<AppState>) -> Result<(usize, Vec<(String, AppError)>), AppError> {
+    let mut synced = 0;
+    let mut failed = Vec::new();
+
+    let mut entries = tokio::fs::read_dir(templates_dir()?).await?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.is_dir() {
+            match update_template_in_db(&path, app_state).await {
+                Ok(_) => synced += 1,
+                Err(e) => failed.push((path.to_string_lossy().into_owned(), e)),
+            }
+        }
+    }
+
+    Ok((synced, failed))
+}
+
+// hash of everything the preview is rendered from
+fn content_hash(path: &Path) -> Result<String, AppError> {
+    let mut hasher = Sha256::new();
+    for file in ["index.html", "styles/styles.css", "js/script.js"] {
+        hasher.update(Sha256::digest(fs::read(path.join(file))?));
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 // invoked by the background task to add a template to the db
-pub async fn update_template_in_db(path: &Path, app_state: &Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut template = Template::from_metadata(path).await?;
-    template.dir_path = path.to_string_lossy().to_string();
+pub async fn update_template_in_db(path: &Path, app_state: &Arc<AppState>) -> Result<(), AppError> {
+    let mut template = Template::from_metadata(path)?;
     let templates: Collection<Template> = app_state.db.collection("templates");
 
+    let existing = templates.find_one(doc! { "name": &template.name }, None).await?;
+
+    // a change to what the template is made of makes it a new version.
+    // templates synced before hashing have no hash, so theirs is taken as the first version.
+    template.version = match &existing {
+        Some(existing) if existing.content_hash.is_empty() || existing.content_hash == template.content_hash => existing.version,
+        Some(existing) => existing.version + 1,
+        None => 1,
+    };
+
+    // rendering is the expensive part, so the old preview is kept if nothing it's made from has changed
+    let previews = app_state.previews.settings().files(&path.join("previews"), "");
+    template.previews = match existing {
+        Some(existing)
+            if existing.content_hash == template.content_hash
+                && existing.dir_path == template.dir_path
+                && existing.previews == previews
+                && existing.previews.exists() => existing.previews,
+        _ => template.build_preview(app_state).await?,
+    };
+
+    // uploads keep track of the owner, a rescan of the directory shouldn't reset it
+    let mut fields = to_document(&template)?;
+    fields.remove("owner");
+
     templates.update_one(
         doc! { "name": &template.name },
-        doc! { "$set": to_bson(&template)? },
+        doc! { "$set": fields },
         mongodb::options::UpdateOptions::builder().upsert(true).build(),
     ).await?;
 
+    // read back for the id, which a new template only gets from the upsert
+    if let Some(template) = templates.find_one(doc! { "name": &template.name }, None).await? {
+        TemplateVersion::record(&template, app_state).await?;
+    }
     Ok(())
 }
 
-// if ever we wanna implement deleting templates. code is redundant rn, so commenting to reduce build size.
-// pub async fn delete_template_from_db(path: &Path, app_state: &Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
-//     let metadata_path = path.join("metadata.json");
-//     if metadata_path.exists() {
-//         let metadata_content = fs::read_to_string(metadata_path)?;
-//         let template: Template = serde_json::from_str(&metadata_content)?;
-//         let templates: Collection<Template> = app_state.db.collection("templates");
-
-//         templates.delete_one(doc! { "name": &template.name }, None).await?;
-//     }
-
-//     Ok(())
-// }
+// invoked by the background task when a template's directory is gone. metadata.json went with it,
+// so the template is found by where it lived.
+pub async fn delete_template_from_db(path: &Path, app_state: &Arc<AppState>) -> Result<(), AppError> {
+    let templates: Collection<Template> = app_state.db.collection("templates");
+    templates.delete_one(doc! { "dir_path": path.to_string_lossy().as_ref() }, None).await?;
+    Ok(())
+}
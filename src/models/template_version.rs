@@ -0,0 +1,223 @@
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::{FindOneOptions, FindOptions};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use similar::TextDiff;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::models::revision::Revision;
+use crate::models::schema::Field;
+use crate::models::site::{read_to_string, Site};
+use crate::models::template::Template;
+use crate::services::paths;
+use crate::error::AppError;
+use crate::AppState;
+
+// the source files every site gets from its template, by the name they're reported with
+const SOURCE_FILES: [(&str, &str); 3] = [
+    ("html", "index.html"),
+    ("css", "styles/styles.css"),
+    ("js", "js/script.js"),
+];
+
+// a snapshot of a template's source, taken every time it changes.
+// just like revisions, the files are small enough to be stored inline.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TemplateVersion {
+    #[serde(rename = "_id")]
+    pub id: Option<ObjectId>,
+    pub template: ObjectId,
+    pub version: u32,
+    pub time: String,
+    pub html: String,
+    pub css: String,
+    pub js: String,
+    #[serde(default)]
+    pub fields: Vec<Field>,
+}
+
+// what gets listed, without the source
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TemplateVersionSummary {
+    pub version: u32,
+    pub time: String,
+}
+
+// the template a site was built from, and the version it's at
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TemplateRef {
+    pub id: ObjectId,
+    pub version: u32,
+}
+
+impl TemplateVersion {
+    // snapshots the template as its current version, unless that version has already been recorded
+    pub async fn record(template: &Template, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let template_id = template.id.ok_or_else(|| AppError::NotFound("template does not have an id".into()))?;
+        let versions: Collection<TemplateVersion> = app_state.db.collection("template_versions");
+        if versions
+            .find_one(doc! { "template": template_id, "version": template.version }, None)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let base = Path::new(&template.dir_path);
+        let version = TemplateVersion {
+            id: Some(ObjectId::new()),
+            template: template_id,
+            version: template.version,
+            time: Utc::now().to_rfc3339(),
+            html: read_to_string(&base.join("index.html"))?,
+            css: read_to_string(&base.join("styles").join("styles.css"))?,
+            js: read_to_string(&base.join("js").join("script.js"))?,
+            fields: template.fields.clone(),
+        };
+        versions.insert_one(version, None).await?;
+        Ok(())
+    }
+
+    pub async fn from(template_id: ObjectId, version: u32, app_state: &Arc<AppState>) -> Result<Self, AppError> {
+        let versions: Collection<TemplateVersion> = app_state.db.collection("template_versions");
+        versions
+            .find_one(doc! { "template": template_id, "version": version }, None)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("version {} of this template isn't available", version)))
+    }
+
+    pub async fn latest(template_id: ObjectId, app_state: &Arc<AppState>) -> Result<Self, AppError> {
+        let versions: Collection<TemplateVersion> = app_state.db.collection("template_versions");
+        let options = FindOneOptions::builder().sort(doc! { "version": -1 }).build();
+        versions
+            .find_one(doc! { "template": template_id }, options)
+            .await?
+            .ok_or_else(|| AppError::NotFound("this template has no versions".into()))
+    }
+
+    // latest first
+    pub async fn list(template_id: ObjectId, app_state: &Arc<AppState>) -> Result<Vec<TemplateVersionSummary>, AppError> {
+        let versions: Collection<TemplateVersionSummary> = app_state.db.collection("template_versions");
+        let options = FindOptions::builder()
+            .sort(doc! { "version": -1 })
+            .projection(doc! { "_id": 0, "version": 1, "time": 1 })
+            .build();
+
+        Ok(versions.find(doc! { "template": template_id }, options).await?.try_collect().await?)
+    }
+
+    fn source(&self) -> [&str; 3] {
+        [&self.html, &self.css, &self.js]
+    }
+}
+
+// what it takes to bring a site up to the latest version of its template. the site's own edits are
+// kept as long as the template didn't change the same file, otherwise that file is a conflict.
+pub struct Upgrade {
+    pub from: TemplateVersion,
+    pub to: TemplateVersion,
+    site: [String; 3],
+}
+
+impl Upgrade {
+    pub async fn new(site: &Site, app_state: &Arc<AppState>) -> Result<Upgrade, AppError> {
+        let template = site
+            .template
+            .as_ref()
+            .ok_or_else(|| AppError::NotFound("This site was created before templates were versioned".into()))?;
+
+        let from = TemplateVersion::from(template.id, template.version, app_state).await?;
+        let to = TemplateVersion::latest(template.id, app_state).await?;
+
+        let base = Path::new(&site.path);
+        let site = SOURCE_FILES.map(|(_, file)| read_to_string(&base.join(file)).unwrap_or_default());
+        Ok(Upgrade { from, to, site })
+    }
+
+    // for every source file: its name, its path, what it was when the site was created, what it is now & what the site has
+    fn files(&self) -> impl Iterator<Item = (&'static str, &'static str, &str, &str, &str)> {
+        let (from, to) = (self.from.source(), self.to.source());
+        SOURCE_FILES
+            .iter()
+            .enumerate()
+            .map(move |(i, (name, path))| (*name, *path, from[i], to[i], self.site[i].as_str()))
+    }
+
+    pub fn up_to_date(&self) -> bool {
+        self.to.version <= self.from.version
+    }
+
+    // the files both the template & the site changed since the site was created
+    pub fn conflicts(&self) -> Vec<&'static str> {
+        self.files()
+            .filter(|(_, _, old, new, current)| old != new && current != old && current != new)
+            .map(|(_, path, _, _, _)| path)
+            .collect()
+    }
+
+    // unified diffs of what changed in the template, along with what would keep it from being applied
+    pub fn summary(&self) -> serde_json::Value {
+        let from = format!("v{}", self.from.version);
+        let to = format!("v{}", self.to.version);
+        let changes: serde_json::Map<String, serde_json::Value> = self
+            .files()
+            .map(|(name, _, old, new, _)| {
+                let diff = TextDiff::from_lines(old, new).unified_diff().header(&from, &to).to_string();
+                (name.to_string(), json!(diff))
+            })
+            .collect();
+
+        json!({
+            "from": self.from.version,
+            "to": self.to.version,
+            "up_to_date": self.up_to_date(),
+            "changes": changes,
+            "conflicts": self.conflicts(),
+        })
+    }
+
+    // writes the latest version of the template to the site & records it as a new revision
+    pub async fn apply(self, site: &mut Site, author: ObjectId, app_state: &Arc<AppState>) -> Result<u32, AppError> {
+        let site_id = site.id.ok_or_else(|| AppError::NotFound("site does not have an id".into()))?;
+        if self.up_to_date() {
+            return Ok(self.from.version);
+        }
+
+        let conflicts = self.conflicts();
+        if !conflicts.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "{} changed in the template but was edited on this site as well, so it can't be upgraded automatically",
+                conflicts.join(", ")
+            )));
+        }
+
+        // a file the site didn't touch is taken from the template, anything else stays as the site has it
+        let base = Path::new(&site.path);
+        for (_, path, old, new, current) in self.files() {
+            if old != new && current == old {
+                File::create(paths::join(base, path)?)?.write_all(new.as_bytes())?;
+            }
+        }
+
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        sites.update_one(
+            doc! { "_id": site_id },
+            doc! { "$set": { "template.version": self.to.version } },
+            None
+        ).await?;
+        if let Some(template) = site.template.as_mut() {
+            template.version = self.to.version;
+        }
+
+        Revision::create(site, author, app_state).await?;
+        site.clone().update_preview(app_state).await?;
+        Ok(self.to.version)
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::{Duration, Utc};
+use mongodb::bson::{doc, oid::ObjectId, to_bson};
+use mongodb::options::FindOneOptions;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::AppState;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Purpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+// a single use, expiring token that gets mailed to the user. only its hash is stored.
+#[derive(Serialize, Deserialize)]
+pub struct Token {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    pub purpose: Purpose,
+    pub hash: String,
+    pub created: i64,
+    pub expires: i64,
+    pub used: bool,
+}
+
+impl Token {
+    // returns the token to be sent to the user
+    pub async fn issue(
+        user_id: ObjectId,
+        purpose: Purpose,
+        ttl: i64,
+        app_state: &Arc<AppState>,
+    ) -> Result<String, AppError> {
+        let raw = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let token = Token {
+            id: ObjectId::new(),
+            user: user_id,
+            purpose,
+            hash: hash_token(&raw),
+            created: Utc::now().timestamp(),
+            expires: (Utc::now() + Duration::seconds(ttl)).timestamp(),
+            used: false,
+        };
+
+        let tokens: Collection<Token> = app_state.db.collection("tokens");
+        tokens.insert_one(token, None).await?;
+        Ok(raw)
+    }
+
+    // when a token was last issued to this user for this purpose, used to throttle resends
+    pub async fn last_issued(
+        user_id: ObjectId,
+        purpose: Purpose,
+        app_state: &Arc<AppState>,
+    ) -> Result<Option<i64>, AppError> {
+        let tokens: Collection<Token> = app_state.db.collection("tokens");
+        let options = FindOneOptions::builder().sort(doc! { "created": -1 }).build();
+        Ok(tokens
+            .find_one(doc! { "user": user_id, "purpose": to_bson(&purpose)? }, options)
+            .await?
+            .map(|token| token.created))
+    }
+
+    // marks the token as used & returns the user it was issued to
+    pub async fn redeem(
+        raw: &str,
+        purpose: Purpose,
+        app_state: &Arc<AppState>,
+    ) -> Result<ObjectId, AppError> {
+        let tokens: Collection<Token> = app_state.db.collection("tokens");
+        let token = tokens
+            .find_one_and_update(
+                doc! {
+                    "hash": hash_token(raw),
+                    "purpose": to_bson(&purpose)?,
+                    "used": false,
+                    "expires": { "$gt": Utc::now().timestamp() }
+                },
+                doc! { "$set": { "used": true } },
+                None,
+            )
+            .await?
+            .ok_or_else(|| AppError::Validation("this link is invalid or has expired".into()))?;
+        Ok(token.user)
+    }
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
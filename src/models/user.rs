@@ -1,8 +1,42 @@
-use mongodb::bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId, to_bson};
+use mongodb::Collection;
 use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use utoipa::ToSchema;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
 
-use super::site::Data;
+use super::revision::Revision;
+use super::session::Session;
+use super::site::{Data, Site};
+use super::moderation::Moderation;
+use super::token::Token;
+use crate::error::AppError;
+use crate::services::paths;
+use crate::AppState;
+
+// everyone is a user. admins can do everything the other roles can.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    TemplateAuthor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::TemplateAuthor => "template_author",
+            Role::Admin => "admin",
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct User {
@@ -19,6 +53,182 @@ pub struct User {
     pub pronouns: Option<String>,
     pub phone: Option<String>,
     pub image: Option<String>,
+    #[serde(default)]
+    pub verified: bool,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    // set by an admin. a suspended user can't login & their page isn't served
+    #[serde(default)]
+    pub suspended: Option<Moderation>,
+}
+
+impl User {
+    pub fn has_role(&self, role: Role) -> bool {
+        role == Role::User || self.roles.contains(&role) || self.roles.contains(&Role::Admin)
+    }
+
+    // the profile as site data, under the selectors the templates use for it
+    pub fn profile_data(&self, api_url: &str) -> Vec<Data> {
+        let item = |selector: &str, value: Option<&String>, link: Option<&str>| {
+            value.filter(|value| !value.is_empty()).map(|value| Data {
+                selector: Some(String::from(selector)),
+                value: Some(value.clone()),
+                link: link.map(String::from),
+                items: Vec::new(),
+            })
+        };
+        let image = self.image.as_ref().map(|_| format!("{}/user/dp?username={}", api_url, self.username));
+
+        let mut data: Vec<Data> = [
+            item("name", Some(&self.name), None),
+            item("username", Some(&self.username), None),
+            item("email", Some(&self.email), Some("mailto:")),
+            // the templates show the date of birth under age
+            item("age", self.dob.as_ref(), None),
+            item("pronouns", self.pronouns.as_ref(), None),
+            item("bio", self.bio.as_ref(), None),
+            item("phone", self.phone.as_ref(), Some("tel:")),
+            item("image", image.as_ref(), None),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        data.extend(self.links.iter().filter(|link| link.selector.is_some()).cloned());
+        data
+    }
+
+    pub fn dir(id: &ObjectId) -> Result<PathBuf, std::env::VarError> {
+        Ok(paths::zitefy_dir()?.join("users").join(id.to_hex()))
+    }
+
+    // logs the user out everywhere & takes their page off zitefy.com
+    pub async fn suspend(&self, reason: String, by: ObjectId, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let moderation = Moderation { reason, time: Utc::now().to_rfc3339(), by };
+
+        let users: Collection<User> = app_state.db.collection("users");
+        users.update_one(
+            doc! { "_id": self._id },
+            doc! { "$set": { "suspended": to_bson(&moderation)?, "quick_response": null } },
+            None
+        ).await?;
+
+        Session::revoke_all(self._id, app_state).await
+    }
+
+    // the active site goes back up as it was last published, unless it was taken down on its own
+    pub async fn unsuspend(&self, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let mut update = doc! { "suspended": null };
+        if let Some(site_id) = self.active {
+            let site = Site::from(site_id, app_state).await?;
+            if let (Some(published), None) = (site.published, site.taken_down) {
+                update.insert("quick_response", published.html);
+            }
+        }
+
+        let users: Collection<User> = app_state.db.collection("users");
+        users.update_one(doc! { "_id": self._id }, doc! { "$set": update }, None).await?;
+        Ok(())
+    }
+
+    // deletes the account along with every site, session & file that belongs to it
+    pub async fn delete(self, app_state: &Arc<AppState>) -> Result<(), AppError> {
+        let sites: Collection<Site> = app_state.db.collection("sites");
+        for site in Site::get_by_user(self._id, app_state).await? {
+            site.delete(app_state).await?;
+        }
+        sites.delete_many(doc! { "user": self._id }, None).await?;
+
+        let sessions: Collection<Session> = app_state.db.collection("sessions");
+        sessions.delete_many(doc! { "user": self._id }, None).await?;
+
+        let tokens: Collection<Token> = app_state.db.collection("tokens");
+        tokens.delete_many(doc! { "user": self._id }, None).await?;
+
+        let user_dir = User::dir(&self._id)?;
+        if user_dir.exists() {
+            fs::remove_dir_all(user_dir)?;
+        }
+
+        let users: Collection<User> = app_state.db.collection("users");
+        users.delete_one(doc! { "_id": self._id }, None).await?;
+        Ok(())
+    }
+
+    // everything we have on the user in a zip: the user document without the password hash, the profile
+    // picture, and the source, data, resources & revisions of every site. the file is already unlinked.
+    pub async fn export(self, app_state: &Arc<AppState>) -> Result<File, AppError> {
+        let sites = Site::get_by_user(self._id, app_state).await?;
+        let mut revisions = Vec::new();
+        for site in &sites {
+            revisions.push(Revision::list_full(site.id.unwrap_or_else(ObjectId::new), app_state).await?);
+        }
+
+        let user_dir = User::dir(&self._id)?;
+        let mut user = serde_json::to_value(&self)?;
+        if let Some(user) = user.as_object_mut() {
+            user.remove("passwd");
+        }
+
+        let file = tokio::task::spawn_blocking(move || -> Result<File, AppError> {
+            let mut zip = ZipWriter::new(tempfile::tempfile()?);
+            write_export(&mut zip, &user, &user_dir, &sites, &revisions)?;
+
+            let mut file = zip.finish()?;
+            file.rewind()?;
+            Ok(file)
+        }).await??;
+
+        Ok(file)
+    }
+}
+
+fn write_export(
+    zip: &mut ZipWriter<File>,
+    user: &serde_json::Value,
+    user_dir: &Path,
+    sites: &[Site],
+    revisions: &[Vec<Revision>],
+) -> Result<(), AppError> {
+    let options = SimpleFileOptions::default();
+    zip.start_file("user.json", options)?;
+    zip.write_all(serde_json::to_string_pretty(user)?.as_bytes())?;
+    zip_dir(zip, user_dir, "profile", None)?;
+
+    for (site, revisions) in sites.iter().zip(revisions) {
+        let prefix = format!("sites/{}", site.id.unwrap_or_else(ObjectId::new).to_hex());
+        zip.start_file(format!("{}/site.json", prefix), options)?;
+        zip.write_all(serde_json::to_string_pretty(site)?.as_bytes())?;
+        zip.start_file(format!("{}/revisions.json", prefix), options)?;
+        zip.write_all(serde_json::to_string_pretty(revisions)?.as_bytes())?;
+        zip_dir(zip, Path::new(&site.path), &format!("{}/source", prefix), Some("previews"))?;
+    }
+    Ok(())
+}
+
+// adds everything in a directory to the zip under the given prefix
+fn zip_dir<W: Write + Seek>(zip: &mut ZipWriter<W>, dir: &Path, prefix: &str, exclude: Option<&str>) -> io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+
+        if path.is_dir() {
+            if exclude == Some(name.as_str()) {
+                continue;
+            }
+            zip_dir(zip, &path, &format!("{}/{}", prefix, name), None)?;
+        } else {
+            zip.start_file(format!("{}/{}", prefix, name), SimpleFileOptions::default())?;
+            io::copy(&mut File::open(&path)?, zip)?;
+        }
+    }
+    Ok(())
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -39,6 +249,38 @@ pub struct LoginData {
     pub password: String,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct PasswordData {
+    #[schema(example = "pass123")]
+    pub current_password: String,
+    #[schema(example = "pass456")]
+    pub new_password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetRequestData {
+    #[schema(example = "athul@email.com")]
+    pub email: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetData {
+    pub token: String,
+    #[schema(example = "pass456")]
+    pub password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeleteAccountData {
+    #[schema(example = "pass123")]
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    pub token: String,
+}
+
 #[derive(Deserialize, ToSchema)]
 pub struct EditData {
     pub name: Option<String>,
@@ -60,4 +302,34 @@ pub struct UserDataResponse {
     pub links: Vec<Data>,
     pub pronouns: Option<String>,
     pub phone: Option<String>,
+    pub verified: bool,
+    pub roles: Vec<Role>,
+}
+
+// what admins see when looking through users
+#[derive(Serialize, ToSchema)]
+pub struct UserSummary {
+    pub id: ObjectId,
+    pub username: String,
+    pub email: String,
+    pub name: String,
+    pub verified: bool,
+    pub roles: Vec<Role>,
+    pub active: Option<ObjectId>,
+    pub suspended: Option<Moderation>,
+}
+
+impl From<User> for UserSummary {
+    fn from(user: User) -> Self {
+        UserSummary {
+            id: user._id,
+            username: user.username,
+            email: user.email,
+            name: user.name,
+            verified: user.verified,
+            roles: user.roles,
+            active: user.active,
+            suspended: user.suspended,
+        }
+    }
 }
@@ -3,6 +3,7 @@ use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use mongodb::{bson::doc, Collection, Database};
 use std::{sync::Arc, env, path::PathBuf};
 
+use crate::models::site::Site;
 use crate::models::user::User;
 
 // the server at zitefy.com
@@ -13,6 +14,10 @@ pub async fn domain_server(
     req: HttpRequest,
 ) -> impl Responder {
     let path = req.path().trim_start_matches('/');
+
+    if let Some(token) = path.strip_prefix("draft/") {
+        return serve_draft(&app_state, token).await;
+    }
     
     // Search for user in the database
     let users_collection: Collection<User> = app_state.collection("users");
@@ -20,6 +25,10 @@ pub async fn domain_server(
     
     match users_collection.find_one(filter, None).await {
         Ok(Some(user)) => {
+            if user.suspended.is_some() {
+                return taken_down_notice();
+            }
+
             if let Some(quick_response) = user.quick_response {
                 if !quick_response.is_empty() {
                     // Return quick_response as HTML
@@ -28,6 +37,13 @@ pub async fn domain_server(
                         .body(quick_response);
                 }
             }
+
+            if let Some(site_id) = user.active {
+                let sites: Collection<Site> = app_state.collection("sites");
+                if let Ok(Some(Site { taken_down: Some(_), .. })) = sites.find_one(doc! { "_id": site_id }, None).await {
+                    return taken_down_notice();
+                }
+            }
         }
         Ok(None) => {
             // return HttpResponse::Found()
@@ -47,4 +63,36 @@ pub async fn domain_server(
         Ok(file) => file.into_response(&req),
         Err(_) => HttpResponse::NotFound().body("File not found"),
     }
-}
\ No newline at end of file
+}
+
+// renders the current draft of the site with this token. these aren't meant to be indexed.
+async fn serve_draft(db: &Database, token: &str) -> HttpResponse {
+    let sites: Collection<Site> = db.collection("sites");
+
+    match sites.find_one(doc! { "draft_token": token }, None).await {
+        Ok(Some(site)) if site.taken_down.is_some() => taken_down_notice(),
+        Ok(Some(site)) => match site.get_html().await {
+            Ok(html) => HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .append_header(("X-Robots-Tag", "noindex"))
+                .body(html),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
+        Ok(None) => HttpResponse::NotFound().body("This draft doesn't exist."),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// served in place of a page that an admin has taken down
+fn taken_down_notice() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .content_type("text/html; charset=utf-8")
+        .append_header(("X-Robots-Tag", "noindex"))
+        .body(concat!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>zitefy</title></head>",
+            "<body style=\"font-family: sans-serif; text-align: center; padding-top: 20vh\">",
+            "<h1>This page has been taken down</h1>",
+            "<p>It was found to break the rules of <a href=\"https://zitefy.com\">zitefy</a>.</p>",
+            "</body></html>"
+        ))
+}
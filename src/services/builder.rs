@@ -0,0 +1,179 @@
+use html5ever::{namespace_url, ns, LocalName, QualName};
+use kuchikiki::traits::*;
+use kuchikiki::{Attribute, ExpandedName, NodeData, NodeRef};
+
+use crate::models::site::Data;
+
+// builds a single html string from the given html, css & js, the same way scripts/builder.js used to.
+// css goes into a <style> in the head, js into a <script type="module"> at the end of the body,
+// and every data item is applied to the first element with a matching id.
+// items of a repeated group are rendered from the <template> inside that element, one clone per item.
+pub fn build(html: &str, css: &str, js: &str, data: &[Data]) -> String {
+    let document = kuchikiki::parse_html().one(html);
+
+    if let Ok(head) = document.select_first("head") {
+        let style = new_element("style", vec![]);
+        style.append(NodeRef::new_text(css));
+        head.as_node().append(style);
+    }
+
+    if let Ok(body) = document.select_first("body") {
+        let script = new_element("script", vec![("type", "module")]);
+        script.append(NodeRef::new_text(js));
+        body.as_node().append(script);
+    }
+
+    for item in data {
+        apply(&document, item, false);
+    }
+
+    document.to_string()
+}
+
+// applies a data item to the element its selector points to. at the top level that's the element with
+// the id, inside an item of a repeated group it's the element with a matching data-field.
+fn apply(scope: &NodeRef, item: &Data, nested: bool) {
+    let selector = match item.selector.as_deref() {
+        Some(selector) if !selector.is_empty() => selector,
+        _ => return,
+    };
+
+    let css = match nested {
+        true => format!("[data-field=\"{}\"]", selector),
+        false => format!("#{}", selector),
+    };
+    let element = match scope.select_first(&css) {
+        Ok(element) => element.as_node().clone(),
+        Err(_) => return,
+    };
+
+    if item.items.is_empty() {
+        set(&element, item);
+    } else {
+        repeat(&element, item);
+    }
+}
+
+// the <template> in the element is cloned for every item of the group & the item's data applied to the clone.
+// for lists, a value without a selector goes to the first element of the clone.
+fn repeat(container: &NodeRef, group: &Data) {
+    let template = container
+        .children()
+        .find(|child| child.as_element().is_some_and(|element| &*element.name.local == "template"));
+    let (template, contents) = match template {
+        Some(template) => match template.as_element().and_then(|element| element.template_contents.clone()) {
+            Some(contents) => (template, contents),
+            None => return,
+        },
+        None => return,
+    };
+    template.detach();
+
+    for entry in &group.items {
+        let fragment = NodeRef::new(NodeData::DocumentFragment);
+        for child in contents.children() {
+            fragment.append(deep_clone(&child));
+        }
+
+        for item in entry {
+            match item.selector.as_deref() {
+                Some(selector) if !selector.is_empty() => apply(&fragment, item, true),
+                _ => {
+                    if let Some(root) = fragment.children().find(|child| child.as_element().is_some()) {
+                        set(&root, item);
+                    }
+                },
+            }
+        }
+
+        for child in fragment.children().collect::<Vec<_>>() {
+            container.append(child);
+        }
+    }
+}
+
+fn set(node: &NodeRef, item: &Data) {
+    // items without a value are left untouched, so the element keeps
+    // whatever the template had in it.
+    let value = match item.value.as_deref() {
+        Some(value) if !value.is_empty() => value,
+        _ => return,
+    };
+    let element = match node.as_element() {
+        Some(element) => element,
+        None => return,
+    };
+
+    let (attribute, url) = match &*element.name.local {
+        "img" | "video" | "audio" | "source" | "track" | "iframe" | "embed" | "script" => {
+            ("src", value.to_string())
+        }
+        // an unset link ends up as the literal "null". data only ever reached builder.js through serde,
+        // which writes a missing link as null too, so that's what the templates were built against.
+        _ => match item.link.as_deref() {
+            Some(link) if !link.is_empty() => ("href", format!("{}{}", link, value)),
+            Some(link) => ("href", link.to_string()),
+            None => ("href", String::from("null")),
+        },
+    };
+
+    let display = {
+        let mut attributes = element.attributes.borrow_mut();
+        attributes.insert(attribute, url);
+        attributes.get("data-display") == Some("true")
+    };
+
+    if display {
+        for child in node.children().collect::<Vec<_>>() {
+            child.detach();
+        }
+        node.append(NodeRef::new_text(value));
+    }
+}
+
+fn deep_clone(node: &NodeRef) -> NodeRef {
+    let clone = NodeRef::new(node.data().clone());
+    for child in node.children() {
+        clone.append(deep_clone(&child));
+    }
+    clone
+}
+
+fn new_element(name: &str, attributes: Vec<(&str, &str)>) -> NodeRef {
+    NodeRef::new_element(
+        QualName::new(None, ns!(html), LocalName::from(name)),
+        attributes.into_iter().map(|(name, value)| {
+            (
+                ExpandedName::new(ns!(), LocalName::from(name)),
+                Attribute { prefix: None, value: value.to_string() },
+            )
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::build;
+    use crate::models::site::Data;
+
+    // every directory in tests/fixtures/builder is a site along with what builder.js made of it.
+    // a link that's missing from data.json is given to builder.js as null, the way the api always passed it.
+    #[test]
+    fn matches_builder_js() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/builder");
+        let mut count = 0;
+        for entry in fs::read_dir(fixtures).unwrap() {
+            let dir = entry.unwrap().path();
+            let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+            let data: Vec<Data> = serde_json::from_str(&read("data.json")).unwrap();
+
+            let html = build(&read("index.html"), &read("styles.css"), &read("script.js"), &data);
+            assert_eq!(html, read("expected.html").trim_end(), "{}", dir.display());
+            count += 1;
+        }
+        assert!(count > 0, "no fixtures were found");
+    }
+}
@@ -0,0 +1,239 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, Lines};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use crate::error::AppError;
+use crate::services::preview::{ImageFormat, Output, Renderer};
+
+// requests to our own API from a page being rendered are blocked
+const BLOCKED_URLS: [&str; 1] = ["*api.zitefy.com*"];
+
+// drives chrome over the DevTools protocol, without node or puppeteer in between.
+// every render gets a browser of its own, which goes away along with the future.
+pub struct CdpRenderer {
+    chrome: PathBuf,
+}
+
+impl CdpRenderer {
+    pub fn new(chrome: PathBuf) -> Self {
+        CdpRenderer { chrome }
+    }
+
+    async fn launch(&self, profile: &Path) -> Result<(Child, String), AppError> {
+        let mut chrome = Command::new(&self.chrome)
+            .arg("--headless=new")
+            .arg("--no-sandbox")
+            .arg("--disable-gpu")
+            .arg("--hide-scrollbars")
+            .arg("--remote-debugging-port=0")
+            .arg(format!("--user-data-dir={}", profile.display()))
+            .arg("about:blank")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // chrome picks the port & says where it's listening on stderr
+        let stderr = chrome.stderr.take().ok_or_else(|| AppError::Render("Failed to read from chrome".into()))?;
+        let mut lines = BufReader::new(stderr).lines();
+        let url = devtools_url(&mut lines).await?;
+
+        // a full pipe would block chrome, so the rest of it is read & thrown away
+        tokio::spawn(async move { while let Ok(Some(_)) = lines.next_line().await {} });
+        Ok((chrome, url))
+    }
+}
+
+async fn devtools_url<R: AsyncRead + Unpin>(lines: &mut Lines<BufReader<R>>) -> Result<String, AppError> {
+    let mut output = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        if let Some(url) = line.strip_prefix("DevTools listening on ") {
+            return Ok(url.trim().to_string());
+        }
+        output.push(line);
+    }
+    log::error!("Chrome exited before it could be connected to: {}", output.join("\n"));
+    Err(AppError::Render("Failed to start the browser".into()))
+}
+
+#[async_trait]
+impl Renderer for CdpRenderer {
+    fn name(&self) -> &'static str {
+        "cdp"
+    }
+
+    async fn render(&self, html: &Path, outputs: &[Output]) -> Result<(), AppError> {
+        let profile = TempDir::new()?;
+        let (_chrome, url) = self.launch(profile.path()).await?;
+        let mut browser = Session::connect(&url).await?;
+
+        let target = browser.call("Target.createTarget", json!({ "url": "about:blank" })).await?;
+        let attached = browser
+            .call("Target.attachToTarget", json!({ "targetId": target["targetId"], "flatten": true }))
+            .await?;
+        let session_id = attached["sessionId"]
+            .as_str()
+            .ok_or_else(|| AppError::Render("Chrome didn't attach to the page".into()))?;
+        browser.session_id = Some(session_id.to_string());
+
+        browser.call("Page.enable", json!({})).await?;
+        browser.call("Page.setLifecycleEventsEnabled", json!({ "enabled": true })).await?;
+        browser.call("Network.enable", json!({})).await?;
+        browser.call("Network.setBlockedURLs", json!({ "urls": BLOCKED_URLS })).await?;
+
+        let navigated = browser
+            .call("Page.navigate", json!({ "url": format!("file://{}", html.display()) }))
+            .await?;
+        if let Some(error) = navigated["errorText"].as_str() {
+            return Err(AppError::Render(format!("Failed to load the page: {}", error)));
+        }
+        // like puppeteer's networkidle0, the page is done once nothing has been loading for a bit
+        let loader = navigated["loaderId"].clone();
+        browser
+            .wait_for(|event| {
+                event["method"] == "Page.lifecycleEvent"
+                    && event["params"]["name"] == "networkIdle"
+                    && event["params"]["loaderId"] == loader
+            })
+            .await?;
+
+        for output in outputs {
+            match output {
+                Output::Screenshot { path, viewport, format, quality } => {
+                    let mut metrics = json!({
+                        "width": viewport.width,
+                        "height": viewport.height,
+                        "deviceScaleFactor": viewport.scale,
+                        "mobile": false,
+                    });
+                    browser.call("Emulation.setDeviceMetricsOverride", metrics.clone()).await?;
+
+                    // the viewport is stretched over the whole page, so that all of it is in the screenshot
+                    if viewport.full_page {
+                        let layout = browser.call("Page.getLayoutMetrics", json!({})).await?;
+                        if let Some(height) = layout["cssContentSize"]["height"].as_f64() {
+                            metrics["height"] = json!(height.ceil() as u64);
+                            browser.call("Emulation.setDeviceMetricsOverride", metrics).await?;
+                        }
+                    }
+
+                    let mut params = json!({ "format": format.extension() });
+                    // png is lossless & doesn't take a quality
+                    if *format != ImageFormat::Png {
+                        params["quality"] = json!(quality);
+                    }
+                    let screenshot = browser.call("Page.captureScreenshot", params).await?;
+                    write_data(path, &screenshot)?;
+                },
+                Output::Pdf { path, options } => {
+                    let (width, height) = options.paper_size();
+                    let margin = options.margin_inches();
+                    let pdf = browser
+                        .call("Page.printToPDF", json!({
+                            "paperWidth": width,
+                            "paperHeight": height,
+                            "marginTop": margin,
+                            "marginBottom": margin,
+                            "marginLeft": margin,
+                            "marginRight": margin,
+                            "printBackground": true,
+                        }))
+                        .await?;
+                    write_data(path, &pdf)?;
+                },
+            }
+        }
+
+        browser.session_id = None;
+        let _ = browser.call("Browser.close", json!({})).await;
+        Ok(())
+    }
+}
+
+// screenshots & pdfs come back base64 encoded
+fn write_data(path: &Path, result: &Value) -> Result<(), AppError> {
+    let data = result["data"]
+        .as_str()
+        .ok_or_else(|| AppError::Render("Chrome didn't return anything".into()))?;
+    let data = STANDARD.decode(data).map_err(|e| AppError::Render(e.to_string()))?;
+    fs::write(path, data)?;
+    Ok(())
+}
+
+// a connection to the browser. commands go to the page once a session is set, & to the browser otherwise.
+struct Session {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+    session_id: Option<String>,
+    // events that came in while waiting for the result of a command
+    events: VecDeque<Value>,
+}
+
+impl Session {
+    async fn connect(url: &str) -> Result<Session, AppError> {
+        let (socket, _) = connect_async(url).await.map_err(render_error)?;
+        Ok(Session { socket, next_id: 0, session_id: None, events: VecDeque::new() })
+    }
+
+    async fn call(&mut self, method: &str, params: Value) -> Result<Value, AppError> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let mut command = json!({ "id": id, "method": method, "params": params });
+        if let Some(session_id) = &self.session_id {
+            command["sessionId"] = json!(session_id);
+        }
+        self.socket.send(Message::Text(command.to_string())).await.map_err(render_error)?;
+
+        loop {
+            let message = self.next().await?;
+            if message["id"] != id {
+                if message.get("method").is_some() {
+                    self.events.push_back(message);
+                }
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                return Err(AppError::Render(format!("{} failed: {}", method, error["message"])));
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+
+    async fn next(&mut self) -> Result<Value, AppError> {
+        while let Some(message) = self.socket.next().await {
+            if let Message::Text(text) = message.map_err(render_error)? {
+                return Ok(serde_json::from_str(&text)?);
+            }
+        }
+        Err(AppError::Render("Chrome closed the connection".into()))
+    }
+
+    // the first event that matches, including any that came in earlier
+    async fn wait_for(&mut self, matches: impl Fn(&Value) -> bool) -> Result<Value, AppError> {
+        if let Some(i) = self.events.iter().position(&matches) {
+            return Ok(self.events.remove(i).unwrap_or_default());
+        }
+        loop {
+            let message = self.next().await?;
+            if matches(&message) {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+fn render_error(e: tokio_tungstenite::tungstenite::Error) -> AppError {
+    AppError::Render(e.to_string())
+}
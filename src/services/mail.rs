@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::path::PathBuf;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::Config;
+
+pub type MailError = Box<dyn std::error::Error + Send + Sync>;
+
+// anything that can deliver a plain text mail to a user
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), MailError>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, username: &str, password: &str, from: &str) -> Result<Self, MailError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)?
+            .credentials(Credentials::new(username.to_string(), password.to_string()))
+            .build();
+        Ok(SmtpMailer { transport, from: from.to_string() })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(self.from.parse()?)
+            .to(to.parse()?)
+            .subject(subject)
+            .body(body.to_string())?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+// for local development & tests. mails are appended to a file if one is given, otherwise logged.
+pub struct LogMailer {
+    path: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        LogMailer { path }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), MailError> {
+        let mail = format!("[{}] to: {}\nsubject: {}\n\n{}\n\n", Utc::now().to_rfc3339(), to, subject, body);
+        match &self.path {
+            Some(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
+                file.write_all(mail.as_bytes()).await?;
+            },
+            None => log::info!("{}", mail),
+        }
+        Ok(())
+    }
+}
+
+// MAIL_TRANSPORT picks the implementation, the log mailer is used unless smtp is asked for
+pub fn from_config(config: &Config) -> Box<dyn Mailer> {
+    if config.mail_transport == "smtp" {
+        match SmtpMailer::new(&config.smtp_host, &config.smtp_username, &config.smtp_password, &config.mail_from) {
+            Ok(mailer) => return Box::new(mailer),
+            Err(e) => eprintln!("Failed to set up SMTP, falling back to logging mails: {}", e),
+        }
+    }
+    Box::new(LogMailer::new(config.mail_log.clone()))
+}
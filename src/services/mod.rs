@@ -1,2 +1,8 @@
+pub mod builder;
+pub mod cdp;
+pub mod mail;
+pub mod paths;
 pub mod preview;
-pub mod tempfiles;
\ No newline at end of file
+pub mod preview_cache;
+pub mod republish;
+pub mod tempfiles;
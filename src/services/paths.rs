@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::AppError;
+
+// every path built from something a client sent goes through here, so that it can't end up
+// outside of the directory it's meant for.
+
+// where everything zitefy keeps on disk lives
+pub fn zitefy_dir() -> Result<PathBuf, std::env::VarError> {
+    Ok(Path::new(&std::env::var("HOME")?).join(".zitefy"))
+}
+
+// a single file name, like that of an asset. no directories & nothing hidden.
+pub fn file_name(name: &str) -> Result<&str, AppError> {
+    let invalid = name.is_empty()
+        || name.len() > 255
+        || name.starts_with('.')
+        || name.trim() != name
+        || name.contains(['/', '\\', '\0', ':']);
+    if invalid {
+        return Err(AppError::Validation(format!("{} isn't a valid file name", name)));
+    }
+    Ok(name)
+}
+
+// joins a relative path from a client onto base. empty & "." components are dropped, while absolute paths,
+// ".." & anything that goes through a symlink are rejected.
+pub fn join(base: &Path, relative: &str) -> Result<PathBuf, AppError> {
+    let invalid = || AppError::Validation(format!("{} isn't a valid path", relative));
+    if relative.contains('\0') || relative.starts_with(['/', '\\']) {
+        return Err(invalid());
+    }
+
+    let mut path = base.to_path_buf();
+    let mut depth = 0;
+    for part in relative.split(['/', '\\']) {
+        if part.is_empty() || part == "." {
+            continue;
+        }
+
+        // anything that isn't a plain name, like .. or a windows drive, is out
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if !part.contains(':') => {},
+            _ => return Err(invalid()),
+        }
+
+        path.push(part);
+        depth += 1;
+        if is_symlink(&path) {
+            return Err(invalid());
+        }
+    }
+
+    if depth == 0 {
+        return Err(invalid());
+    }
+    Ok(path)
+}
+
+// for paths that were stored earlier rather than sent by a client. they have to resolve to somewhere inside base.
+pub fn contained(base: &Path, path: &Path) -> Result<PathBuf, AppError> {
+    let resolved = path.canonicalize()?;
+    if !resolved.starts_with(base.canonicalize()?) {
+        return Err(AppError::Forbidden(format!("{} is outside of where it should be", path.display())));
+    }
+    Ok(resolved)
+}
+
+pub fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    use super::{contained, file_name, join};
+
+    #[test]
+    fn join_stays_inside_base() {
+        let base = Path::new("/base");
+        assert_eq!(join(base, "index.html").unwrap(), base.join("index.html"));
+        assert_eq!(join(base, "styles/styles.css").unwrap(), base.join("styles").join("styles.css"));
+        assert_eq!(join(base, "./js//script.js").unwrap(), base.join("js").join("script.js"));
+        assert_eq!(join(base, "js\\script.js").unwrap(), base.join("js").join("script.js"));
+
+        for attack in [
+            "",
+            ".",
+            "..",
+            "../",
+            "../etc/passwd",
+            "a/../../x",
+            "a/..",
+            "..\\..\\x",
+            "a\\..\\..\\x",
+            "/etc/passwd",
+            "\\etc\\passwd",
+            "a\0b",
+            "C:",
+            "C:\\Windows",
+            "c:/x",
+            "a/C:x",
+        ] {
+            assert!(join(base, attack).is_err(), "{:?} was joined", attack);
+        }
+    }
+
+    #[test]
+    fn join_rejects_symlinks() {
+        let base = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        fs::create_dir(base.path().join("real")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), base.path().join("link")).unwrap();
+
+        assert!(join(base.path(), "real/file").is_ok());
+        assert!(join(base.path(), "link").is_err());
+        assert!(join(base.path(), "link/file").is_err());
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name("photo.png").unwrap(), "photo.png");
+        assert_eq!(file_name("my photo (1).png").unwrap(), "my photo (1).png");
+
+        let long = "a".repeat(256);
+        for attack in [
+            "",
+            ".",
+            "..",
+            ".env",
+            ".htaccess",
+            "../x.png",
+            "a/b.png",
+            "a\\b.png",
+            "a\0.png",
+            "C:x.png",
+            " x.png",
+            "x.png ",
+            long.as_str(),
+        ] {
+            assert!(file_name(attack).is_err(), "{:?} was accepted", attack);
+        }
+    }
+
+    #[test]
+    fn contained_resolves_inside_base() {
+        let base = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        fs::create_dir(base.path().join("dir")).unwrap();
+        fs::write(base.path().join("dir").join("file"), "").unwrap();
+        fs::write(outside.path().join("secret"), "").unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret"), base.path().join("link")).unwrap();
+
+        assert!(contained(base.path(), &base.path().join("dir").join("file")).is_ok());
+        assert!(contained(base.path(), &base.path().join("dir").join("..").join("dir").join("file")).is_ok());
+        assert!(contained(base.path(), &base.path().join("..").join("etc")).is_err());
+        assert!(contained(base.path(), &outside.path().join("secret")).is_err());
+        assert!(contained(base.path(), &base.path().join("link")).is_err());
+        assert!(contained(base.path(), &base.path().join("missing")).is_err());
+    }
+}
@@ -1,69 +1,729 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::ops::Deref;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::fs::File;
+use std::fs::{self, File};
+use std::sync::Arc;
 use tempfile::TempDir;
-use tokio::time::{sleep, Duration};
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot, Mutex, Notify};
+use tokio::time::{self, Duration, Instant};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use utoipa::ToSchema;
+use uuid::Uuid;
+use mongodb::bson::oid::ObjectId;
 
-#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
-pub struct Preview {
-    pub mobile: PathBuf,
-    pub desktop: PathBuf,
+use crate::config::Config;
+use crate::models::site::Data;
+use crate::services::builder;
+use crate::services::cdp::CdpRenderer;
+use crate::services::preview_cache::{copy_to, PreviewCache};
+use crate::services::tempfiles::TempFileService;
+
+use crate::error::AppError;
+
+// how long the status of a finished job sticks around
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(600);
+
+// a size previews are rendered at. anything below a scale of 1 is downscaled, for thumbnails.
+// a full page screenshot goes down to the end of the page instead of stopping at the height.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Viewport {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub scale: f64,
+    pub full_page: bool,
+}
+
+impl Viewport {
+    // name:WIDTHxHEIGHT, followed by @SCALE for a downscaled one and :full for a full page screenshot
+    pub fn parse(spec: &str) -> Result<Viewport, String> {
+        let invalid = || format!("{} isn't a valid viewport, they're given as name:WIDTHxHEIGHT[@SCALE][:full]", spec);
+        let mut parts = spec.trim().split(':');
+        let (name, size) = (parts.next().ok_or_else(invalid)?, parts.next().ok_or_else(invalid)?);
+        let full_page = match parts.next() {
+            Some("full") => true,
+            None => false,
+            _ => return Err(invalid()),
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        // the name ends up in file names & query strings
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(invalid());
+        }
+
+        let (size, scale) = match size.split_once('@') {
+            Some((size, scale)) => (size, scale.parse().map_err(|_| invalid())?),
+            None => (size, 1.0),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let (width, height): (u32, u32) = (width.parse().map_err(|_| invalid())?, height.parse().map_err(|_| invalid())?);
+        if width == 0 || height == 0 || !(scale > 0.0 && scale <= 4.0) {
+            return Err(invalid());
+        }
+
+        Ok(Viewport { name: name.to_string(), width, height, scale, full_page })
+    }
+
+    // a comma separated list, like PREVIEW_VIEWPORTS
+    pub fn parse_all(specs: &str) -> Result<Vec<Viewport>, String> {
+        let viewports = specs
+            .split(',')
+            .filter(|spec| !spec.trim().is_empty())
+            .map(Viewport::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if viewports.is_empty() {
+            return Err(String::from("there has to be at least one viewport"));
+        }
+        for (i, viewport) in viewports.iter().enumerate() {
+            if viewports[..i].iter().any(|other| other.name == viewport.name) {
+                return Err(format!("there's more than one viewport named {}", viewport.name));
+            }
+        }
+        Ok(viewports)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn parse(format: &str) -> Option<ImageFormat> {
+        match format.to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpeg" | "jpg" => Some(ImageFormat::Jpeg),
+            "webp" => Some(ImageFormat::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Webp => "webp",
+        }
+    }
+}
+
+// how previews are rendered on this deployment
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewSettings {
+    pub viewports: Vec<Viewport>,
+    pub format: ImageFormat,
+    // for jpeg & webp, png is lossless
+    pub quality: u8,
+}
+
+impl PreviewSettings {
+    pub fn viewport(&self, name: &str) -> Result<&Viewport, AppError> {
+        self.viewports.iter().find(|viewport| viewport.name == name).ok_or_else(|| {
+            let names: Vec<&str> = self.viewports.iter().map(|viewport| viewport.name.as_str()).collect();
+            AppError::Validation(format!("{} isn't a viewport, it has to be one of {}", name, names.join(", ")))
+        })
+    }
+
+    pub fn file_name(&self, viewport: &Viewport) -> String {
+        format!("{}.{}", viewport.name, self.format.extension())
+    }
+
+    // where the image of every viewport goes, in dir & with an optional prefix
+    pub fn files(&self, dir: &Path, prefix: &str) -> Preview {
+        Preview(
+            self.viewports
+                .iter()
+                .map(|viewport| (viewport.name.clone(), dir.join(format!("{}{}", prefix, self.file_name(viewport)))))
+                .collect(),
+        )
+    }
+}
+
+// the image of every viewport, by its name
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Preview(pub BTreeMap<String, PathBuf>);
+
+impl Preview {
+    pub fn get(&self, viewport: &str) -> Option<&PathBuf> {
+        self.0.get(viewport)
+    }
+
+    pub fn exists(&self) -> bool {
+        self.0.values().all(|path| path.is_file())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PreviewStatus {
+    Queued,
+    Running,
+    // ids of the image of every viewport, served from /preview/{id}
+    Done { images: BTreeMap<String, String> },
+    Failed { error: String },
+    Cancelled,
+}
+
+// the page sizes chrome knows
+const PAGE_SIZES: [&str; 7] = ["A3", "A4", "A5", "Legal", "Letter", "Tabloid", "Ledger"];
+
+// how a pdf is laid out. the margin is a css length, applied to every side.
+#[derive(Debug, Clone, Serialize)]
+pub struct PdfOptions {
+    pub page_size: String,
+    pub margin: String,
+}
+
+impl PdfOptions {
+    pub fn new(page_size: Option<&str>, margin: Option<&str>) -> Result<PdfOptions, AppError> {
+        let page_size = page_size.unwrap_or("A4");
+        let page_size = PAGE_SIZES
+            .iter()
+            .find(|size| size.eq_ignore_ascii_case(page_size))
+            .ok_or_else(|| AppError::Validation(format!("{} isn't a page size, it has to be one of {}", page_size, PAGE_SIZES.join(", "))))?;
+
+        let margin = margin.unwrap_or("10mm");
+        if inches(margin).is_none() {
+            return Err(AppError::Validation(format!("{} isn't a margin, it's a number in mm, cm, in or px", margin)));
+        }
+
+        Ok(PdfOptions { page_size: page_size.to_string(), margin: margin.to_string() })
+    }
+
+    // width & height of a page in inches, which is what the devtools protocol takes
+    pub fn paper_size(&self) -> (f64, f64) {
+        match self.page_size.as_str() {
+            "A3" => (11.69, 16.54),
+            "A5" => (5.83, 8.27),
+            "Legal" => (8.5, 14.0),
+            "Letter" => (8.5, 11.0),
+            "Tabloid" => (11.0, 17.0),
+            "Ledger" => (17.0, 11.0),
+            _ => (8.27, 11.69),
+        }
+    }
+
+    pub fn margin_inches(&self) -> f64 {
+        inches(&self.margin).unwrap_or_default()
+    }
+}
+
+fn inches(length: &str) -> Option<f64> {
+    let (unit, per_inch) = [("mm", 25.4), ("cm", 2.54), ("in", 1.0), ("px", 96.0)]
+        .into_iter()
+        .find(|(unit, _)| length.ends_with(unit))?;
+    let value: f64 = length[..length.len() - unit.len()].parse().ok()?;
+    (0.0..=100.0).contains(&value).then_some(value / per_inch)
+}
+
+enum Task {
+    // screenshots at every viewport, cached under the key. they're copied to the paths if there are any.
+    Screenshots {
+        key: String,
+        paths: Option<Preview>,
+        // rendered even if the key is cached, replacing what's there
+        fresh: bool,
+        result: Option<oneshot::Sender<Result<Preview, AppError>>>,
+    },
+    Pdf {
+        path: PathBuf,
+        options: PdfOptions,
+        result: oneshot::Sender<Result<(), AppError>>,
+    },
+}
+
+struct Job {
+    // only jobs handed out to clients are tracked, the rest are awaited by whoever queued them
+    id: Option<String>,
+    html: String,
+    task: Task,
+    cancel: Arc<Notify>,
+}
+
+struct TrackedJob {
+    // the user who queued it, the only one who gets to see or cancel it
+    owner: ObjectId,
+    status: PreviewStatus,
+    cancel: Arc<Notify>,
+    finished: Option<Instant>,
+}
+
+type Jobs = Arc<Mutex<HashMap<String, TrackedJob>>>;
+
+// renders run chrome, so only a few of them go at once. the rest wait in a bounded queue,
+// & the editor is told to back off when it's full. html that has been rendered before is served from the cache.
+pub struct PreviewQueue {
+    sender: mpsc::Sender<Job>,
+    jobs: Jobs,
+    settings: Arc<PreviewSettings>,
+    renderer: Arc<dyn Renderer>,
+    cache: Arc<PreviewCache>,
+    tempfiles: TempFileService,
+}
+
+impl PreviewQueue {
+    pub fn new(
+        workers: usize,
+        capacity: usize,
+        timeout: Duration,
+        settings: PreviewSettings,
+        renderer: Arc<dyn Renderer>,
+        cache: PreviewCache,
+        tempfiles: TempFileService,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let jobs: Jobs = Arc::new(Mutex::new(HashMap::new()));
+        let settings = Arc::new(settings);
+        let cache = Arc::new(cache);
+
+        for _ in 0..workers.max(1) {
+            let worker = Worker {
+                jobs: jobs.clone(),
+                timeout,
+                settings: settings.clone(),
+                renderer: renderer.clone(),
+                cache: cache.clone(),
+                tempfiles: tempfiles.clone(),
+            };
+            tokio::spawn(worker.work(receiver.clone()));
+        }
+        PreviewQueue { sender, jobs, settings, renderer, cache, tempfiles }
+    }
+
+    pub fn settings(&self) -> &PreviewSettings {
+        &self.settings
+    }
+
+    // renders the html at every viewport into dir & waits for it, for previews that are stored along with a site or template.
+    // fresh skips the cache, for when what's cached is broken or the assets the html loads have changed.
+    pub async fn render(&self, html: String, dir: Option<&Path>, fresh: bool) -> Result<Preview, AppError> {
+        let key = self.cache.key(&html, &self.settings, self.renderer.name());
+        let paths = dir.map(|dir| self.settings.files(dir, ""));
+        if !fresh {
+            if let Some(preview) = cached(&self.cache, &self.settings, &key, paths.as_ref()) {
+                return Ok(preview);
+            }
+        }
+
+        let (result, receiver) = oneshot::channel();
+        self.queue(html, Task::Screenshots { key, paths, fresh, result: Some(result) }).await?;
+        receiver
+            .await
+            .map_err(|_| AppError::Render("The preview was dropped before it was rendered".into()))?
+    }
+
+    // prints the html to a pdf at path & waits for it
+    pub async fn pdf(&self, html: String, path: PathBuf, options: PdfOptions) -> Result<(), AppError> {
+        let (result, receiver) = oneshot::channel();
+        self.queue(html, Task::Pdf { path, options, result }).await?;
+        receiver
+            .await
+            .map_err(|_| AppError::Render("The pdf was dropped before it was rendered".into()))?
+    }
+
+    async fn queue(&self, html: String, task: Task) -> Result<(), AppError> {
+        let job = Job { id: None, html, task, cancel: Arc::new(Notify::new()) };
+        self.sender
+            .send(job)
+            .await
+            .map_err(|_| AppError::Render("The preview queue has shut down".into()))
+    }
+
+    // queues a render for the owner & returns straight away with the id of the job, to check on it with status()
+    pub async fn submit(&self, html: String, owner: ObjectId) -> Result<String, AppError> {
+        let id = Uuid::new_v4().to_string();
+        let cancel = Arc::new(Notify::new());
+        let key = self.cache.key(&html, &self.settings, self.renderer.name());
+
+        let mut jobs = self.jobs.lock().await;
+        jobs.retain(|_, job| job.finished.is_none_or(|finished| finished.elapsed() < FINISHED_JOB_TTL));
+
+        if let Some(preview) = cached(&self.cache, &self.settings, &key, None) {
+            let status = done(&preview, &self.tempfiles).await;
+            jobs.insert(id.clone(), TrackedJob { owner, status, cancel, finished: Some(Instant::now()) });
+            return Ok(id);
+        }
+
+        let task = Task::Screenshots { key, paths: None, fresh: false, result: None };
+        let job = Job { id: Some(id.clone()), html, task, cancel: cancel.clone() };
+        self.sender
+            .try_send(job)
+            .map_err(|_| AppError::RateLimited("Too many previews are being rendered right now, try again in a bit".into()))?;
+        jobs.insert(id.clone(), TrackedJob { owner, status: PreviewStatus::Queued, cancel, finished: None });
+        Ok(id)
+    }
+
+    pub async fn status(&self, id: &str, owner: ObjectId) -> Result<PreviewStatus, AppError> {
+        Ok(owned(self.jobs.lock().await.get(id), owner)?.status.clone())
+    }
+
+    // a queued job is skipped & a running one is stopped. returns false if the job had already finished.
+    pub async fn cancel(&self, id: &str, owner: ObjectId) -> Result<bool, AppError> {
+        let mut jobs = self.jobs.lock().await;
+        let job = owned(jobs.get_mut(id), owner)?;
+        if !matches!(job.status, PreviewStatus::Queued | PreviewStatus::Running) {
+            return Ok(false);
+        }
+
+        job.status = PreviewStatus::Cancelled;
+        job.finished = Some(Instant::now());
+        job.cancel.notify_one();
+        Ok(true)
+    }
+}
+
+// the job, as long as it was queued by the owner
+fn owned<J: Deref<Target = TrackedJob>>(job: Option<J>, owner: ObjectId) -> Result<J, AppError> {
+    let job = job.ok_or_else(|| AppError::NotFound("There's no preview job with this id".into()))?;
+    if job.owner != owner {
+        return Err(AppError::Forbidden("This preview was queued by another user".into()));
+    }
+    Ok(job)
+}
+
+struct Worker {
+    jobs: Jobs,
+    timeout: Duration,
+    settings: Arc<PreviewSettings>,
+    renderer: Arc<dyn Renderer>,
+    cache: Arc<PreviewCache>,
+    tempfiles: TempFileService,
+}
+
+impl Worker {
+    async fn work(self, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) {
+        loop {
+            let job = receiver.lock().await.recv().await;
+            let Some(job) = job else { return };
+
+            if let Some(id) = &job.id {
+                match self.jobs.lock().await.get_mut(id) {
+                    Some(tracked) if matches!(tracked.status, PreviewStatus::Queued) => tracked.status = PreviewStatus::Running,
+                    // cancelled while it was waiting
+                    _ => continue,
+                }
+            }
+
+            match job.task {
+                Task::Screenshots { key, paths, fresh, result } => {
+                    // the same html might have been rendered while this job was waiting
+                    let hit = match fresh {
+                        true => None,
+                        false => cached(&self.cache, &self.settings, &key, paths.as_ref()),
+                    };
+                    let rendered = match hit {
+                        Some(preview) => Ok(preview),
+                        None => {
+                            let render = render(&*self.renderer, &self.cache, &self.settings, &job.html, &key, paths.as_ref());
+                            self.run(&job.cancel, render).await
+                        },
+                    };
+                    if let Some(id) = &job.id {
+                        self.finish(id, &rendered).await;
+                    }
+                    if let Some(result) = result {
+                        let _ = result.send(rendered);
+                    }
+                },
+                Task::Pdf { path, options, result } => {
+                    let render = generate_pdf(&*self.renderer, &job.html, &path, &options);
+                    let _ = result.send(self.run(&job.cancel, render).await);
+                },
+            }
+        }
+    }
+
+    // dropping the render kills the browser along with it
+    async fn run<T>(&self, cancel: &Notify, render: impl Future<Output = Result<T, AppError>>) -> Result<T, AppError> {
+        tokio::select! {
+            result = time::timeout(self.timeout, render) => result.unwrap_or_else(|_| {
+                Err(AppError::Render(format!("Rendering took longer than {} seconds", self.timeout.as_secs())))
+            }),
+            _ = cancel.notified() => Err(AppError::Render("Rendering was cancelled".into())),
+        }
+    }
+
+    async fn finish(&self, id: &str, result: &Result<Preview, AppError>) {
+        let status = match result {
+            Ok(preview) => done(preview, &self.tempfiles).await,
+            Err(AppError::Render(message)) => PreviewStatus::Failed { error: message.clone() },
+            Err(e) => {
+                log::error!("Failed to render preview {}: {}", id, e);
+                PreviewStatus::Failed { error: String::from("Internal error, contact admin.") }
+            },
+        };
+
+        if let Some(tracked) = self.jobs.lock().await.get_mut(id) {
+            if !matches!(tracked.status, PreviewStatus::Cancelled) {
+                tracked.status = status;
+                tracked.finished = Some(Instant::now());
+            }
+        }
+    }
+}
+
+// the cached images, copied to the paths if there are any. a miss if they were evicted in the meantime.
+fn cached(cache: &PreviewCache, settings: &PreviewSettings, key: &str, paths: Option<&Preview>) -> Option<Preview> {
+    let cached = cache.get(key, settings)?;
+    match paths {
+        Some(paths) => copy_to(&cached, paths).ok().map(|_| paths.clone()),
+        None => Some(cached),
+    }
 }
 
-// if a path is provided, the preview images will be stored to that path, otherwise generates a temporarry file
-// useful for generating permenant and temporarry images (for template previews)
-pub async fn generate_preview(
+async fn render(
+    renderer: &dyn Renderer,
+    cache: &PreviewCache,
+    settings: &PreviewSettings,
     html: &str,
-    paths: Option<Preview>,
-) -> Result<Preview, Box<dyn std::error::Error>> {
-    // Create a temporary directory
+    key: &str,
+    paths: Option<&Preview>,
+) -> Result<Preview, AppError> {
+    let staging = cache.staging()?;
+    let rendered = settings.files(staging.path(), "");
+    generate_preview(renderer, html, &rendered, settings).await?;
+
+    let cached = cache.store(key, rendered, settings).await?;
+    match paths {
+        Some(paths) => {
+            copy_to(&cached, paths)?;
+            Ok(paths.clone())
+        },
+        None => Ok(cached),
+    }
+}
+
+async fn done(preview: &Preview, tempfiles: &TempFileService) -> PreviewStatus {
+    let mut images = BTreeMap::new();
+    for (viewport, path) in &preview.0 {
+        images.insert(viewport.clone(), tempfiles.add_file(path.clone()).await);
+    }
+    PreviewStatus::Done { images }
+}
+
+// takes screenshots of the html at every viewport, to the given paths.
+// goes through the PreviewQueue, nothing else should call this directly.
+async fn generate_preview(renderer: &dyn Renderer, html: &str, paths: &Preview, settings: &PreviewSettings) -> Result<(), AppError> {
+    let screenshots: Vec<Output> = settings
+        .viewports
+        .iter()
+        .filter_map(|viewport| {
+            Some(Output::Screenshot {
+                path: paths.get(&viewport.name)?.clone(),
+                viewport: viewport.clone(),
+                format: settings.format,
+                quality: settings.quality,
+            })
+        })
+        .collect();
+    render_page(renderer, html, &screenshots).await
+}
+
+async fn generate_pdf(renderer: &dyn Renderer, html: &str, path: &Path, options: &PdfOptions) -> Result<(), AppError> {
+    render_page(renderer, html, &[Output::Pdf { path: path.to_path_buf(), options: options.clone() }]).await
+}
+
+async fn render_page(renderer: &dyn Renderer, html: &str, outputs: &[Output]) -> Result<(), AppError> {
     let temp_dir = TempDir::new()?;
     let html_path = temp_dir.path().join("preview.html");
 
-    let (mobile_path, desktop_path) = match paths {
-        Some(path) => (path.mobile, path.desktop),
-        None => (
-            temp_dir.path().join("mobile_preview.png"),
-            temp_dir.path().join("desktop_preview.png"),
-        ),
-    };
-
-    // Write HTML to a temporary file
     let mut file = File::create(&html_path)?;
     file.write_all(html.as_bytes())?;
 
-    Command::new("bun")
-        .arg("run")
-        .arg("scripts/screenshot.js")
-        .arg(&html_path)
-        .arg(&mobile_path)
-        .arg(&desktop_path)
-        .output()?;
+    renderer.render(&html_path, outputs).await
+}
+
+// what a renderer makes of a page
+#[derive(Debug, Clone)]
+pub enum Output {
+    Screenshot {
+        path: PathBuf,
+        viewport: Viewport,
+        format: ImageFormat,
+        quality: u8,
+    },
+    Pdf {
+        path: PathBuf,
+        options: PdfOptions,
+    },
+}
+
+// anything that can load a page & take screenshots of it or print it
+#[async_trait]
+pub trait Renderer: Send + Sync {
+    // part of what previews are cached under, so that switching renderers doesn't serve what another one rendered
+    fn name(&self) -> &'static str;
+
+    // loads the html file & writes every output. dropping the future has to stop the browser.
+    async fn render(&self, html: &Path, outputs: &[Output]) -> Result<(), AppError>;
+}
+
+// runs scripts/screenshot.js with bun, which drives chrome through puppeteer
+pub struct PuppeteerRenderer {
+    chrome: Option<PathBuf>,
+}
+
+impl PuppeteerRenderer {
+    pub fn new(chrome: Option<PathBuf>) -> Self {
+        PuppeteerRenderer { chrome }
+    }
+}
+
+#[async_trait]
+impl Renderer for PuppeteerRenderer {
+    fn name(&self) -> &'static str {
+        "puppeteer"
+    }
+
+    async fn render(&self, html: &Path, outputs: &[Output]) -> Result<(), AppError> {
+        // what the script takes, one entry per output
+        let outputs: Vec<serde_json::Value> = outputs
+            .iter()
+            .map(|output| match output {
+                Output::Screenshot { path, viewport, format, quality } => json!({
+                    "path": path,
+                    "width": viewport.width,
+                    "height": viewport.height,
+                    "scale": viewport.scale,
+                    "fullPage": viewport.full_page,
+                    "type": format,
+                    "quality": quality,
+                }),
+                Output::Pdf { path, options } => json!({ "path": path, "pdf": options }),
+            })
+            .collect();
+
+        let mut command = Command::new("bun");
+        command
+            .arg("run")
+            .arg("scripts/screenshot.js")
+            .arg(html)
+            .arg(serde_json::to_string(&outputs)?)
+            .kill_on_drop(true);
+        // puppeteer uses the chrome it downloaded unless it's told otherwise
+        if let Some(chrome) = &self.chrome {
+            command.env("CHROME_PATH", chrome);
+        }
 
-    // Schedule cleanup after 2 minutes
-    tokio::spawn(async move {
-        sleep(Duration::from_secs(120)).await;
-        if let Err(e) = temp_dir.close() {
-            eprintln!("Error cleaning up temporary directory: {}", e);
+        let output = command.output().await?;
+        // what the script prints can have paths & such in it, so it only goes to the logs
+        if !output.status.success() {
+            log::error!("The screenshot script failed: {}", String::from_utf8_lossy(&output.stderr));
+            return Err(AppError::Render("Failed to render the page".into()));
         }
-    });
+        Ok(())
+    }
+}
+
+// 1x1 images & a blank page
+const PLACEHOLDER_PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+const PLACEHOLDER_JPEG: &str = "/9j/2wBDAAEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQH/wAALCAABAAEBAREA/8QAFAABAAAAAAAAAAAAAAAAAAAAAP/EABQQAQAAAAAAAAAAAAAAAAAAAAD/2gAIAQEAAD8AP//Z";
+const PLACEHOLDER_WEBP: &str = "UklGRhoAAABXRUJQVlA4TA0AAAAvAAAAEAcQERGIiP4HAA==";
+
+// writes the same output for any html, without a browser. for tests & CI, where there's no chrome around.
+pub struct PlaceholderRenderer;
+
+#[async_trait]
+impl Renderer for PlaceholderRenderer {
+    fn name(&self) -> &'static str {
+        "placeholder"
+    }
+
+    async fn render(&self, _: &Path, outputs: &[Output]) -> Result<(), AppError> {
+        for output in outputs {
+            match output {
+                Output::Screenshot { path, format, .. } => {
+                    let image = match format {
+                        ImageFormat::Png => PLACEHOLDER_PNG,
+                        ImageFormat::Jpeg => PLACEHOLDER_JPEG,
+                        ImageFormat::Webp => PLACEHOLDER_WEBP,
+                    };
+                    let image = STANDARD.decode(image).map_err(|e| AppError::Render(e.to_string()))?;
+                    fs::write(path, image)?;
+                },
+                Output::Pdf { path, options } => fs::write(path, placeholder_pdf(options))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// a single blank page of the right size
+fn placeholder_pdf(options: &PdfOptions) -> Vec<u8> {
+    let (width, height) = options.paper_size();
+    let objects = [
+        String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+        String::from("<< /Type /Pages /Kids [3 0 R] /Count 1 >>"),
+        format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.0} {:.0}] >>", width * 72.0, height * 72.0),
+    ];
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+
+    let xref = pdf.len();
+    pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref));
+    pdf.into_bytes()
+}
 
-    Ok(Preview {
-        mobile: mobile_path, 
-        desktop: desktop_path
-    })
+// the implementation PREVIEW_RENDERER picks
+#[derive(Debug, Clone, PartialEq)]
+pub enum RendererKind {
+    Puppeteer,
+    Cdp(PathBuf),
+    Placeholder,
 }
 
-// just a wrapper around scripts/builder.js
+impl RendererKind {
+    pub fn parse(name: &str, chrome: Option<&Path>) -> Result<RendererKind, String> {
+        match (name, chrome) {
+            ("puppeteer", _) => Ok(RendererKind::Puppeteer),
+            ("cdp", Some(chrome)) => Ok(RendererKind::Cdp(chrome.to_path_buf())),
+            ("cdp", None) => Err(String::from("the cdp renderer needs CHROME_PATH to be set")),
+            ("placeholder", _) => Ok(RendererKind::Placeholder),
+            _ => Err(format!("{} isn't a renderer, it's puppeteer, cdp or placeholder", name)),
+        }
+    }
+}
+
+pub fn renderer_from_config(config: &Config) -> Arc<dyn Renderer> {
+    match &config.preview_renderer {
+        RendererKind::Puppeteer => Arc::new(PuppeteerRenderer::new(config.chrome_path.clone())),
+        RendererKind::Cdp(chrome) => Arc::new(CdpRenderer::new(chrome.clone())),
+        RendererKind::Placeholder => Arc::new(PlaceholderRenderer),
+    }
+}
+
+// reads the html, css, js & data files from disk and builds a single html string out of them.
+// falls back to scripts/default.json when no data is given, useful for template previews.
 pub fn build_html_string(
     html: PathBuf,
     css: PathBuf,
     js: PathBuf,
     data: Option<PathBuf>,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<String, AppError> {
     let data = match data {
         Some(path) => path,
         None => {
@@ -72,21 +732,173 @@ pub fn build_html_string(
         },
     };
 
-    let output = Command::new("bun")
-        .arg("run")
-        .arg("scripts/builder.js")
-        .arg(&html)
-        .arg(&css)
-        .arg(&js)
-        .arg(&data)
-        .output()?;
+    let data: Vec<Data> = serde_json::from_str(&fs::read_to_string(&data)?)
+        .map_err(|e| AppError::Validation(format!("{} isn't valid data: {}", data.display(), e)))?;
 
-    if !output.status.success() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            String::from_utf8_lossy(&output.stderr),
-        )));
+    Ok(builder::build(
+        &fs::read_to_string(html)?,
+        &fs::read_to_string(css)?,
+        &fs::read_to_string(js)?,
+        &data,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // the placeholder, counting how many renders actually get to it
+    #[derive(Default)]
+    struct Counting {
+        renders: AtomicUsize,
     }
 
-    Ok(String::from_utf8(output.stdout)?)
+    #[async_trait]
+    impl Renderer for Counting {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn render(&self, html: &Path, outputs: &[Output]) -> Result<(), AppError> {
+            self.renders.fetch_add(1, Ordering::SeqCst);
+            PlaceholderRenderer.render(html, outputs).await
+        }
+    }
+
+    // takes longer than the queue gives it
+    struct Slow;
+
+    #[async_trait]
+    impl Renderer for Slow {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        async fn render(&self, html: &Path, outputs: &[Output]) -> Result<(), AppError> {
+            time::sleep(Duration::from_secs(30)).await;
+            PlaceholderRenderer.render(html, outputs).await
+        }
+    }
+
+    const OWNER: ObjectId = ObjectId::from_bytes([1; 12]);
+
+    fn queue(dir: &Path, renderer: Arc<dyn Renderer>) -> PreviewQueue {
+        let settings = PreviewSettings {
+            viewports: Viewport::parse_all("mobile:412x915,desktop:1280x800").unwrap(),
+            format: ImageFormat::Png,
+            quality: 80,
+        };
+        let cache = PreviewCache::new(dir.join("cache"), 1024 * 1024);
+        PreviewQueue::new(2, 4, Duration::from_secs(1), settings, renderer, cache, TempFileService::new())
+    }
+
+    async fn finished(queue: &PreviewQueue, id: &str) -> PreviewStatus {
+        for _ in 0..100 {
+            match queue.status(id, OWNER).await.unwrap() {
+                PreviewStatus::Queued | PreviewStatus::Running => time::sleep(Duration::from_millis(10)).await,
+                status => return status,
+            }
+        }
+        panic!("the job never finished");
+    }
+
+    #[tokio::test]
+    async fn renders_every_viewport() {
+        let dir = TempDir::new().unwrap();
+        let queue = queue(dir.path(), Arc::new(PlaceholderRenderer));
+        let out = dir.path().join("site");
+        fs::create_dir(&out).unwrap();
+
+        let preview = queue.render(String::from("<p>hi</p>"), Some(&out), false).await.unwrap();
+        assert_eq!(preview.0.keys().collect::<Vec<_>>(), ["desktop", "mobile"]);
+        assert_eq!(preview.get("mobile").unwrap(), &out.join("mobile.png"));
+        assert!(preview.exists());
+
+        let png = fs::read(preview.get("desktop").unwrap()).unwrap();
+        assert_eq!(png, STANDARD.decode(PLACEHOLDER_PNG).unwrap());
+    }
+
+    #[tokio::test]
+    async fn cached_html_isnt_rendered_again() {
+        let dir = TempDir::new().unwrap();
+        let renderer = Arc::new(Counting::default());
+        let queue = queue(dir.path(), renderer.clone());
+
+        queue.render(String::from("<p>hi</p>"), None, false).await.unwrap();
+        queue.render(String::from("<p>hi</p>"), None, false).await.unwrap();
+        assert_eq!(renderer.renders.load(Ordering::SeqCst), 1);
+
+        queue.render(String::from("<p>bye</p>"), None, false).await.unwrap();
+        assert_eq!(renderer.renders.load(Ordering::SeqCst), 2);
+
+        // unless it's asked for
+        queue.render(String::from("<p>hi</p>"), None, true).await.unwrap();
+        assert_eq!(renderer.renders.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn submitted_jobs_can_be_checked_on() {
+        let dir = TempDir::new().unwrap();
+        let queue = queue(dir.path(), Arc::new(PlaceholderRenderer));
+
+        let id = queue.submit(String::from("<p>hi</p>"), OWNER).await.unwrap();
+        match finished(&queue, &id).await {
+            PreviewStatus::Done { images } => assert_eq!(images.keys().collect::<Vec<_>>(), ["desktop", "mobile"]),
+            status => panic!("the job ended up {:?}", status),
+        }
+        assert!(!queue.cancel(&id, OWNER).await.unwrap());
+        assert!(matches!(queue.status("nothing", OWNER).await, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn slow_renders_time_out_or_get_cancelled() {
+        let dir = TempDir::new().unwrap();
+        let queue = queue(dir.path(), Arc::new(Slow));
+
+        let result = queue.render(String::from("<p>hi</p>"), None, false).await;
+        assert!(matches!(result, Err(AppError::Render(e)) if e.contains("longer than 1 seconds")));
+
+        let id = queue.submit(String::from("<p>bye</p>"), OWNER).await.unwrap();
+        assert!(queue.cancel(&id, OWNER).await.unwrap());
+        assert!(matches!(finished(&queue, &id).await, PreviewStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_gets_at_a_job() {
+        let dir = TempDir::new().unwrap();
+        let queue = queue(dir.path(), Arc::new(Slow));
+        let someone = ObjectId::new();
+
+        let id = queue.submit(String::from("<p>hi</p>"), OWNER).await.unwrap();
+        assert!(matches!(queue.status(&id, someone).await, Err(AppError::Forbidden(_))));
+        assert!(matches!(queue.cancel(&id, someone).await, Err(AppError::Forbidden(_))));
+        assert!(matches!(queue.status(&id, OWNER).await, Ok(PreviewStatus::Queued | PreviewStatus::Running)));
+    }
+
+    #[tokio::test]
+    async fn prints_pdfs() {
+        let dir = TempDir::new().unwrap();
+        let queue = queue(dir.path(), Arc::new(PlaceholderRenderer));
+        let path = dir.path().join("site.pdf");
+
+        let options = PdfOptions::new(Some("letter"), Some("1in")).unwrap();
+        queue.pdf(String::from("<p>hi</p>"), path.clone(), options).await.unwrap();
+
+        let pdf = fs::read_to_string(path).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains("/MediaBox [0 0 612 792]"));
+        assert!(pdf.ends_with("%%EOF\n"));
+    }
+
+    #[test]
+    fn renderers_are_checked() {
+        let chrome = Path::new("/usr/bin/chromium");
+        assert_eq!(RendererKind::parse("puppeteer", None), Ok(RendererKind::Puppeteer));
+        assert_eq!(RendererKind::parse("placeholder", None), Ok(RendererKind::Placeholder));
+        assert_eq!(RendererKind::parse("cdp", Some(chrome)), Ok(RendererKind::Cdp(chrome.to_path_buf())));
+        assert!(RendererKind::parse("cdp", None).is_err());
+        assert!(RendererKind::parse("chrome", Some(chrome)).is_err());
+    }
 }
@@ -0,0 +1,210 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+
+use crate::error::AppError;
+use crate::services::preview::{Preview, PreviewSettings};
+
+// where renders are written to before they're complete, kept inside the cache so that storing them is a rename
+const STAGING_DIR: &str = ".staging";
+
+// rendered previews, keyed by a hash of the html & the renderer & settings they were rendered with.
+// identical html, like an unchanged site being saved again or a template rescanned, reuses the images.
+// the least recently used images go once the cache is over its size limit.
+pub struct PreviewCache {
+    dir: PathBuf,
+    limit: u64,
+    // eviction lists & removes files, two of them at once would trip over each other
+    evicting: Mutex<()>,
+}
+
+impl PreviewCache {
+    pub fn new(dir: PathBuf, limit: u64) -> Self {
+        if let Err(e) = fs::create_dir_all(dir.join(STAGING_DIR)) {
+            eprintln!("Failed to create the preview cache at {}: {}", dir.display(), e);
+        }
+        PreviewCache { dir, limit, evicting: Mutex::new(()) }
+    }
+
+    pub fn key(&self, html: &str, settings: &PreviewSettings, renderer: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(renderer);
+        hasher.update(serde_json::to_string(settings).unwrap_or_default());
+        hasher.update(html);
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn paths(&self, key: &str, settings: &PreviewSettings) -> Preview {
+        settings.files(&self.dir, &format!("{}-", key))
+    }
+
+    // the cached images for the key, marked as just used
+    pub fn get(&self, key: &str, settings: &PreviewSettings) -> Option<Preview> {
+        let preview = self.paths(key, settings);
+        for path in preview.0.values() {
+            let file = fs::File::options().append(true).open(path).ok()?;
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(preview)
+    }
+
+    // a directory to render into, removed along with whatever is left in it when it's dropped
+    pub fn staging(&self) -> Result<TempDir, AppError> {
+        let dir = self.dir.join(STAGING_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(TempDir::new_in(dir)?)
+    }
+
+    // moves a finished render into the cache & makes room for it
+    pub async fn store(&self, key: &str, rendered: Preview, settings: &PreviewSettings) -> Result<Preview, AppError> {
+        let preview = self.paths(key, settings);
+        for (viewport, path) in &preview.0 {
+            let from = rendered
+                .get(viewport)
+                .ok_or_else(|| AppError::Render(format!("The {} preview wasn't rendered", viewport)))?;
+            fs::rename(from, path)?;
+        }
+        self.evict().await?;
+        Ok(preview)
+    }
+
+    async fn evict(&self) -> Result<(), AppError> {
+        let _evicting = self.evicting.lock().await;
+
+        let mut files = Vec::new();
+        let mut total = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            total += metadata.len();
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+
+        files.sort();
+        for (_, size, path) in files {
+            if total <= self.limit {
+                break;
+            }
+            fs::remove_file(&path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+// copies cached images to where they're kept for good, like the previews of a site
+pub fn copy_to(cached: &Preview, paths: &Preview) -> Result<(), AppError> {
+    for (viewport, to) in &paths.0 {
+        let from = cached
+            .get(viewport)
+            .ok_or_else(|| AppError::NotFound(format!("The {} preview isn't cached", viewport)))?;
+        if let Some(parent) = to.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::services::preview::{ImageFormat, Output, PlaceholderRenderer, Renderer, Viewport};
+
+    fn settings() -> PreviewSettings {
+        PreviewSettings {
+            viewports: Viewport::parse_all("mobile:412x915,desktop:1280x800").unwrap(),
+            format: ImageFormat::Jpeg,
+            quality: 80,
+        }
+    }
+
+    // what a worker does, with the placeholder in place of a browser
+    async fn render(cache: &PreviewCache, key: &str, settings: &PreviewSettings) -> Preview {
+        let staging = cache.staging().unwrap();
+        let rendered = settings.files(staging.path(), "");
+        let outputs: Vec<Output> = settings
+            .viewports
+            .iter()
+            .map(|viewport| Output::Screenshot {
+                path: rendered.get(&viewport.name).unwrap().clone(),
+                viewport: viewport.clone(),
+                format: settings.format,
+                quality: settings.quality,
+            })
+            .collect();
+        PlaceholderRenderer.render(Path::new("index.html"), &outputs).await.unwrap();
+        cache.store(key, rendered, settings).await.unwrap()
+    }
+
+    #[test]
+    fn keys_change_with_what_was_rendered() {
+        let dir = TempDir::new().unwrap();
+        let cache = PreviewCache::new(dir.path().to_path_buf(), 1024);
+        let settings = settings();
+
+        let key = cache.key("<p>hi</p>", &settings, "placeholder");
+        assert_eq!(key, cache.key("<p>hi</p>", &settings, "placeholder"));
+        assert_ne!(key, cache.key("<p>bye</p>", &settings, "placeholder"));
+        assert_ne!(key, cache.key("<p>hi</p>", &settings, "puppeteer"));
+
+        let png = PreviewSettings { format: ImageFormat::Png, ..settings };
+        assert_ne!(key, cache.key("<p>hi</p>", &png, "placeholder"));
+    }
+
+    #[tokio::test]
+    async fn stored_renders_are_found() {
+        let dir = TempDir::new().unwrap();
+        let cache = PreviewCache::new(dir.path().to_path_buf(), 1024 * 1024);
+        let settings = settings();
+
+        assert!(cache.get("missing", &settings).is_none());
+
+        let stored = render(&cache, "key", &settings).await;
+        assert!(stored.exists());
+        assert_eq!(stored.get("mobile").unwrap(), &dir.path().join("key-mobile.jpeg"));
+        assert_eq!(cache.get("key", &settings), Some(stored.clone()));
+
+        // nothing is left behind in staging
+        assert_eq!(fs::read_dir(dir.path().join(STAGING_DIR)).unwrap().count(), 0);
+
+        let site = TempDir::new().unwrap();
+        let paths = settings.files(&site.path().join("previews"), "");
+        copy_to(&stored, &paths).unwrap();
+        assert!(paths.exists());
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_renders_are_evicted() {
+        let dir = TempDir::new().unwrap();
+        let settings = settings();
+
+        // room for two renders, each being two images
+        let sizing = PreviewCache::new(dir.path().join("sizing"), u64::MAX);
+        let image = fs::metadata(render(&sizing, "size", &settings).await.get("mobile").unwrap()).unwrap().len();
+        let cache = PreviewCache::new(dir.path().join("cache"), image * 4);
+
+        render(&cache, "first", &settings).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        render(&cache, "second", &settings).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // using the first one makes the second the oldest
+        assert!(cache.get("first", &settings).is_some());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        render(&cache, "third", &settings).await;
+
+        assert!(cache.get("first", &settings).is_some());
+        assert!(cache.get("second", &settings).is_none());
+        assert!(cache.get("third", &settings).is_some());
+    }
+}
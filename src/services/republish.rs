@@ -0,0 +1,82 @@
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::Collection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+use crate::models::site::Site;
+use crate::models::user::User;
+use crate::error::AppError;
+use crate::AppState;
+
+// keeps the page at zitefy.com/<username> in sync with the user's profile. edits to a site stay in its draft
+// until it's published, only the profile data of what's already live is updated.
+// profile edits come in bursts, so a site is only rendered once nothing has touched it for `delay`.
+pub struct Republisher {
+    delay: Duration,
+    // the latest schedule() call for each site. a pending render only goes ahead if it's still the latest.
+    pending: Arc<Mutex<HashMap<ObjectId, u64>>>,
+}
+
+impl Republisher {
+    pub fn new(delay: Duration) -> Self {
+        Republisher {
+            delay,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn schedule(&self, site_id: ObjectId, app_state: Arc<AppState>) {
+        let generation = {
+            let mut pending = self.pending.lock().await;
+            let generation = pending.get(&site_id).map_or(0, |generation| generation + 1);
+            pending.insert(site_id, generation);
+            generation
+        };
+
+        let pending = self.pending.clone();
+        let delay = self.delay;
+        tokio::spawn(async move {
+            sleep(delay).await;
+
+            {
+                let mut pending = pending.lock().await;
+                if pending.get(&site_id) != Some(&generation) {
+                    return;
+                }
+                pending.remove(&site_id);
+            }
+
+            if let Err(e) = republish(site_id, &app_state).await {
+                eprintln!("Failed to republish site {}: {}", site_id, e);
+            }
+        });
+    }
+
+    // for changes to the profile, which can show up on whatever site the user has active
+    pub async fn schedule_for_user(&self, user_id: ObjectId, app_state: Arc<AppState>) {
+        let users: Collection<User> = app_state.db.collection("users");
+        match users.find_one(doc! { "_id": user_id }, None).await {
+            Ok(Some(User { active: Some(site_id), .. })) => self.schedule(site_id, app_state).await,
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to look up the active site of {}: {}", user_id, e),
+        }
+    }
+}
+
+// only the active site is rendered again, & only if the owner could still publish it themselves
+async fn republish(site_id: ObjectId, app_state: &Arc<AppState>) -> Result<(), AppError> {
+    let mut site = Site::from(site_id, app_state).await?;
+
+    let users: Collection<User> = app_state.db.collection("users");
+    let user = match users.find_one(doc! { "_id": site.user, "active": site_id }, None).await? {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    if user.suspended.is_some() || (app_state.require_verified_email && !user.verified) {
+        return Ok(());
+    }
+
+    site.refresh_published(&user, app_state).await
+}
@@ -21,6 +21,7 @@ impl TempFile {
     }
 }
 
+#[derive(Clone)]
 pub struct TempFileService {
     files: Arc<RwLock<HashMap<String, TempFile>>>,
 }